@@ -1,4 +1,4 @@
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct DoubleBuffered<T> {
     pub front: T,
     pub back: T,
@@ -18,3 +18,10 @@ impl<T: Clone> DoubleBuffered<T> {
         self.front = self.back.clone();
     }
 }
+
+impl<T> DoubleBuffered<T> {
+    #[inline]
+    pub fn swap(&mut self) {
+        std::mem::swap(&mut self.front, &mut self.back);
+    }
+}
@@ -0,0 +1,87 @@
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use std::marker::PhantomData;
+
+use super::index::Index3d;
+use super::{ChunkSize, DefaultSize};
+
+/// Fraction of a voxel's flow kept every tick
+const DECAY: f32 = 0.8;
+/// Flow below this is forgotten
+const EPSILON: f32 = 0.01;
+
+/// Recent liquid movement in voxels per tick, keyed by `i_3d`. Only voxels that moved recently
+/// are stored
+#[derive(Default, Clone, Deref)]
+pub struct Flow<S: ChunkSize = DefaultSize>(#[deref] HashMap<usize, Vec3>, PhantomData<S>);
+
+/// What a tick did to a [`Flow`], enough for [`Flow::undo`] to take it back
+#[derive(Default)]
+pub struct FlowDelta {
+    /// Whether there was anything to decay
+    decayed: bool,
+    /// Entries the decay forgot, as they were before it
+    forgotten: Vec<(usize, Vec3)>,
+    /// `(i_3d, previous)` of every entry the moves wrote, in order
+    moved: Vec<(usize, Option<Vec3>)>,
+}
+
+impl FlowDelta {
+    /// Whether the tick left the flow as it was
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        !self.decayed && self.moved.is_empty()
+    }
+}
+
+impl<S: ChunkSize> Flow<S> {
+    /// Decays every entry, starting the tick's delta
+    pub fn decay(&mut self) -> FlowDelta {
+        let mut delta = FlowDelta {
+            decayed: !self.0.is_empty(),
+            ..default()
+        };
+        self.0.retain(|&i, v| {
+            let prev = *v;
+            *v *= DECAY;
+            let kept = v.length_squared() > EPSILON * EPSILON;
+            if !kept {
+                delta.forgotten.push((i, prev));
+            }
+            kept
+        });
+        delta
+    }
+
+    /// Moves whatever flow `src` had over to `dst` and adds the move itself
+    pub fn record(&mut self, dst: usize, src: usize, delta: &mut FlowDelta) {
+        let d = UVec3::from(dst.xyz::<S>()).as_vec3() - UVec3::from(src.xyz::<S>()).as_vec3();
+        let prev = self.0.remove(&src);
+        delta.moved.push((src, prev));
+
+        let prev_dst = self
+            .0
+            .insert(dst, prev.unwrap_or_default() * DECAY + d * (1. - DECAY));
+        delta.moved.push((dst, prev_dst));
+    }
+
+    /// Takes back the tick `delta` was made by, up to rounding
+    pub fn undo(&mut self, delta: FlowDelta) {
+        for (i, prev) in delta.moved.into_iter().rev() {
+            match prev {
+                Some(v) => self.0.insert(i, v),
+                None => self.0.remove(&i),
+            };
+        }
+
+        for v in self.0.values_mut() {
+            *v /= DECAY;
+        }
+        self.0.extend(delta.forgotten);
+    }
+
+    #[inline]
+    pub fn get(&self, p: impl Index3d) -> Vec3 {
+        self.0.get(&p.i_3d::<S>()).copied().unwrap_or_default()
+    }
+}
@@ -1,158 +1,256 @@
 use bevy::prelude::*;
-use ndshape::{ConstPow2Shape2u32, ConstPow2Shape3u32, ConstShape as _};
+use std::marker::PhantomData;
+use std::ops::Range;
 
-use super::BITS;
+use super::ChunkSize;
 
-pub type Shape3d = ConstPow2Shape3u32<BITS, BITS, BITS>;
-pub type Shape2d = ConstPow2Shape2u32<BITS, BITS>;
+/// Row major layout of the voxels of a chunk of size `S`, X varying fastest
+pub struct Shape3d<S>(PhantomData<S>);
 
-pub const STRIDE_X_3D: usize = 1 << Shape3d::SHIFTS[0];
-pub const STRIDE_Y_3D: usize = 1 << Shape3d::SHIFTS[1];
-pub const STRIDE_Z_3D: usize = 1 << Shape3d::SHIFTS[2];
+impl<S: ChunkSize> Shape3d<S> {
+    pub const STRIDE_X: usize = 1;
+    pub const STRIDE_Y: usize = S::LEN;
+    pub const STRIDE_Z: usize = S::AREA;
 
-pub const STRIDE_Y_2D: usize = 1 << Shape2d::SHIFTS[0];
-pub const STRIDE_Z_2D: usize = 1 << Shape2d::SHIFTS[1];
+    const MASK: u32 = S::LEN_U32 - 1;
 
-const MASK_X: usize = Shape3d::MASKS[0] as usize;
+    #[inline]
+    pub fn linearize([x, y, z]: [u32; 3]) -> u32 {
+        x | (y << S::BITS) | (z << (2 * S::BITS))
+    }
+
+    #[inline]
+    pub fn delinearize(i: u32) -> [u32; 3] {
+        [
+            i & Self::MASK,
+            (i >> S::BITS) & Self::MASK,
+            i >> (2 * S::BITS),
+        ]
+    }
+}
+
+/// Layout of the rows of a chunk of size `S`, Y varying fastest
+pub struct Shape2d<S>(PhantomData<S>);
 
+impl<S: ChunkSize> Shape2d<S> {
+    pub const STRIDE_Y: usize = 1;
+    pub const STRIDE_Z: usize = S::LEN;
+
+    const MASK: u32 = S::LEN_U32 - 1;
+
+    #[inline]
+    pub fn linearize([y, z]: [u32; 2]) -> u32 {
+        y | (z << S::BITS)
+    }
+
+    #[inline]
+    pub fn delinearize(i: u32) -> [u32; 2] {
+        [i & Self::MASK, i >> S::BITS]
+    }
+}
+
+/// A row of a chunk, linearized for the chunk size `S` it's used with
 pub trait Index2d: Copy {
-    fn i_2d(&self) -> usize;
+    fn i_2d<S: ChunkSize>(&self) -> usize;
 
-    fn yz(&self) -> [u32; 2];
+    fn yz<S: ChunkSize>(&self) -> [u32; 2];
 }
 
 impl Index2d for usize {
     #[inline]
-    fn i_2d(&self) -> usize {
+    fn i_2d<S: ChunkSize>(&self) -> usize {
         *self
     }
 
     #[inline]
-    fn yz(&self) -> [u32; 2] {
-        Shape2d::delinearize(*self as u32)
+    fn yz<S: ChunkSize>(&self) -> [u32; 2] {
+        Shape2d::<S>::delinearize(*self as u32)
     }
 }
 
 impl Index2d for [u32; 2] {
     #[inline]
-    fn i_2d(&self) -> usize {
-        Shape2d::linearize(*self) as usize
+    fn i_2d<S: ChunkSize>(&self) -> usize {
+        Shape2d::<S>::linearize(*self) as usize
     }
 
     #[inline]
-    fn yz(&self) -> [u32; 2] {
+    fn yz<S: ChunkSize>(&self) -> [u32; 2] {
         *self
     }
 }
 
 impl Index2d for UVec2 {
     #[inline]
-    fn i_2d(&self) -> usize {
-        self.to_array().i_2d()
+    fn i_2d<S: ChunkSize>(&self) -> usize {
+        self.to_array().i_2d::<S>()
     }
 
     #[inline]
-    fn yz(&self) -> [u32; 2] {
+    fn yz<S: ChunkSize>(&self) -> [u32; 2] {
         self.to_array()
     }
 }
 
+/// A voxel of a chunk, linearized for the chunk size `S` it's used with
 pub trait Index3d: Copy {
-    fn i_3d(&self) -> usize;
+    fn i_3d<S: ChunkSize>(&self) -> usize;
 
-    fn x_and_i_2d(&self) -> (u32, usize);
+    fn x_and_i_2d<S: ChunkSize>(&self) -> (u32, usize);
 
-    fn xyz(&self) -> [u32; 3];
+    fn xyz<S: ChunkSize>(&self) -> [u32; 3];
 }
 
 impl Index3d for usize {
     #[inline]
-    fn i_3d(&self) -> usize {
+    fn i_3d<S: ChunkSize>(&self) -> usize {
         *self
     }
 
     #[inline]
-    fn x_and_i_2d(&self) -> (u32, usize) {
-        ((*self & MASK_X) as u32, *self >> BITS)
+    fn x_and_i_2d<S: ChunkSize>(&self) -> (u32, usize) {
+        ((*self & (S::LEN - 1)) as u32, *self >> S::BITS)
     }
 
     #[inline]
-    fn xyz(&self) -> [u32; 3] {
-        Shape3d::delinearize(*self as u32)
+    fn xyz<S: ChunkSize>(&self) -> [u32; 3] {
+        Shape3d::<S>::delinearize(*self as u32)
     }
 }
 
 impl Index3d for (usize, usize) {
     #[inline]
-    fn i_3d(&self) -> usize {
+    fn i_3d<S: ChunkSize>(&self) -> usize {
         let (x, i_2d) = *self;
-        (i_2d << BITS) | x
+        (i_2d << S::BITS) | x
     }
 
     #[inline]
-    fn x_and_i_2d(&self) -> (u32, usize) {
+    fn x_and_i_2d<S: ChunkSize>(&self) -> (u32, usize) {
         (self.0 as u32, self.1)
     }
 
     #[inline]
-    fn xyz(&self) -> [u32; 3] {
+    fn xyz<S: ChunkSize>(&self) -> [u32; 3] {
         let (x, i_2d) = *self;
-        let [y, z] = i_2d.yz();
+        let [y, z] = i_2d.yz::<S>();
         [x as u32, y, z]
     }
 }
 
 impl Index3d for (u32, usize) {
     #[inline]
-    fn i_3d(&self) -> usize {
+    fn i_3d<S: ChunkSize>(&self) -> usize {
         let (x, i_2d) = *self;
-        (i_2d << BITS) | x as usize
+        (i_2d << S::BITS) | x as usize
     }
 
     #[inline]
-    fn x_and_i_2d(&self) -> (u32, usize) {
+    fn x_and_i_2d<S: ChunkSize>(&self) -> (u32, usize) {
         *self
     }
 
     #[inline]
-    fn xyz(&self) -> [u32; 3] {
+    fn xyz<S: ChunkSize>(&self) -> [u32; 3] {
         let (x, i_2d) = *self;
-        let [y, z] = i_2d.yz();
+        let [y, z] = i_2d.yz::<S>();
         [x, y, z]
     }
 }
 
 impl Index3d for [u32; 3] {
     #[inline]
-    fn i_3d(&self) -> usize {
-        Shape3d::linearize(*self) as usize
+    fn i_3d<S: ChunkSize>(&self) -> usize {
+        Shape3d::<S>::linearize(*self) as usize
     }
 
     #[inline]
-    fn x_and_i_2d(&self) -> (u32, usize) {
+    fn x_and_i_2d<S: ChunkSize>(&self) -> (u32, usize) {
         let [x, y, z] = *self;
-        (x, [y, z].i_2d())
+        (x, [y, z].i_2d::<S>())
     }
 
     #[inline]
-    fn xyz(&self) -> [u32; 3] {
+    fn xyz<S: ChunkSize>(&self) -> [u32; 3] {
         *self
     }
 }
 
 impl Index3d for UVec3 {
     #[inline]
-    fn i_3d(&self) -> usize {
-        self.to_array().i_3d()
+    fn i_3d<S: ChunkSize>(&self) -> usize {
+        self.to_array().i_3d::<S>()
     }
 
     #[inline]
-    fn x_and_i_2d(&self) -> (u32, usize) {
-        self.to_array().x_and_i_2d()
+    fn x_and_i_2d<S: ChunkSize>(&self) -> (u32, usize) {
+        self.to_array().x_and_i_2d::<S>()
     }
 
     #[inline]
-    fn xyz(&self) -> [u32; 3] {
+    fn xyz<S: ChunkSize>(&self) -> [u32; 3] {
         self.to_array()
     }
 }
+
+/// Cubes of `size`³ unpadded voxels tiling a chunk of size `S` from its first unpadded voxel,
+/// clipped where they run into the padding
+pub fn blocks<S: ChunkSize>(size: u32) -> impl Iterator<Item = [Range<u32>; 3]> {
+    let len = S::LEN_U32 - 1;
+    let ranges = move || {
+        (1..len)
+            .step_by(size as usize)
+            .map(move |start| start..(start + size).min(len))
+    };
+
+    ranges().flat_map(move |zs| {
+        ranges().flat_map(move |ys| {
+            let zs = zs.clone();
+            ranges().map(move |xs| [xs, ys.clone(), zs.clone()])
+        })
+    })
+}
+
+/// `i_3d` of every voxel of `block`
+pub fn block_indices<S: ChunkSize>([xs, ys, zs]: [Range<u32>; 3]) -> impl Iterator<Item = usize> {
+    zs.flat_map(move |z| {
+        let xs = xs.clone();
+        ys.clone()
+            .flat_map(move |y| xs.clone().map(move |x| [x, y, z].i_3d::<S>()))
+    })
+}
+
+/// `i_3d` of the voxels of a chunk of size `S` on the side facing `normal`, one of the 6 unit
+/// vectors: the padding if `padding`, the unpadded layer next to it otherwise. Edges and corners
+/// are left out, so the same side of any two chunks lines up voxel for voxel
+pub fn side_indices<S: ChunkSize>(normal: IVec3, padding: bool) -> impl Iterator<Item = usize> {
+    let axis = normal.abs().max_position();
+    let layer = match (normal[axis] > 0, padding) {
+        (true, true) => S::LEN_U32 - 1,
+        (true, false) => S::LEN_U32 - 2,
+        (false, true) => 0,
+        (false, false) => 1,
+    };
+
+    let inner = 1..S::LEN_U32 - 1;
+    let mut block = [inner.clone(), inner.clone(), inner];
+    block[axis] = layer..layer + 1;
+    block_indices::<S>(block)
+}
+
+/// `(padding, border)` pairs of `i_3d` of two chunks of size `S`, the second `offset` away from
+/// the first, one of the 26 around it. The padding of the first holds the same voxels as the
+/// border of the second
+pub fn padding_pairs<S: ChunkSize>(offset: IVec3) -> impl Iterator<Item = (usize, usize)> {
+    let range = |c: i32| match c {
+        1 => S::LEN_U32 - 1..S::LEN_U32,
+        -1 => 0..1,
+        _ => 1..S::LEN_U32 - 1,
+    };
+    let inner = S::LEN_U32 as i32 - 2;
+
+    block_indices::<S>([range(offset.x), range(offset.y), range(offset.z)]).map(move |i| {
+        let border = UVec3::from(i.xyz::<S>()).as_ivec3() - offset * inner;
+        (i, border.as_uvec3().i_3d::<S>())
+    })
+}
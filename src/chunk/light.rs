@@ -0,0 +1,559 @@
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::ops::Range;
+
+use super::index::*;
+use super::masks::Masks;
+use super::storage::Storage;
+use super::{Chunk, ChunkSize, DefaultSize, Row, Voxel, Voxels};
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// Levels lost on top of the usual one for every transparent voxel light passes into
+const TRANSPARENT_ATTENUATION: u8 = 2;
+
+/// Block light a voxel gives off
+#[inline]
+fn emission(v: Option<Voxel>) -> u8 {
+    match v {
+        Some(Voxel::Lava) => MAX_LIGHT - 1,
+        _ => 0,
+    }
+}
+
+/// Levels lost entering voxel `i`, `None` if it blocks light
+#[inline]
+fn cost<S: ChunkSize>(masks: &Masks<S>, i: usize) -> Option<u8> {
+    let (x, i_2d) = i.x_and_i_2d::<S>();
+    let bit = S::Row::ONE << x;
+
+    if masks.some_mask()[i_2d] & bit == S::Row::ZERO {
+        Some(1)
+    } else if masks.transparent_mask[i_2d] & bit != S::Row::ZERO {
+        Some(1 + TRANSPARENT_ATTENUATION)
+    } else {
+        None
+    }
+}
+
+/// Unpadded neighbours of `i` and whether they're below it
+#[inline]
+fn neighbours<S: ChunkSize>(i: usize) -> impl Iterator<Item = (usize, bool)> {
+    let [x, y, z] = i.xyz::<S>();
+    let inner = |c: u32| (1..S::LEN_U32 - 1).contains(&c);
+
+    [
+        (x + 1, y, z, false),
+        (x.wrapping_sub(1), y, z, false),
+        (x, y + 1, z, false),
+        (x, y.wrapping_sub(1), z, true),
+        (x, y, z + 1, false),
+        (x, y, z.wrapping_sub(1), false),
+    ]
+    .into_iter()
+    .filter(move |&(x, y, z, _)| inner(x) && inner(y) && inner(z))
+    .map(|(x, y, z, down)| ([x, y, z].i_3d::<S>(), down))
+}
+
+/// Unpadded voxel nearest to `i`, `i` itself if it isn't padding
+#[inline]
+fn nearest_unpadded<S: ChunkSize>(i: usize) -> usize {
+    i.xyz::<S>().map(|c| c.clamp(1, S::LEN_U32 - 2)).i_3d::<S>()
+}
+
+/// Padding below the top whose nearest unpadded voxel is `i`, top down
+fn padding_around<S: ChunkSize>(i: usize) -> impl Iterator<Item = usize> {
+    let last = S::LEN_U32 - 1;
+    let options = |c: u32| match c {
+        1 => [1, 0],
+        c if c == last - 1 => [c, last],
+        c => [c, c],
+    };
+    let [xs, ys, zs] = i.xyz::<S>().map(options);
+    let [y0, y1] = ys;
+
+    // y1 is below y0 only for the bottom padding
+    let ys = if y1 < y0 { [y0, y1] } else { [y1, y0] };
+    ys.into_iter()
+        .filter(move |&y| y != last)
+        .flat_map(move |y| zs.into_iter().flat_map(move |z| xs.map(|x| [x, y, z])))
+        .map(|p| p.i_3d::<S>())
+        .filter(move |&p| p != i)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Comes down the columns open above the chunk, losing nothing while falling through air
+    Sky,
+    /// Given off by emissive voxels
+    Block,
+}
+
+impl Channel {
+    const ALL: [Self; 2] = [Channel::Sky, Channel::Block];
+
+    #[inline]
+    fn shift(self) -> u32 {
+        match self {
+            Channel::Sky => 4,
+            Channel::Block => 0,
+        }
+    }
+}
+
+/// Flood filled light levels from 0 to [`MAX_LIGHT`].
+///
+/// The sky is assumed to be directly above the open columns, neighbouring chunks don't light each
+/// other. The padding is never flooded, it holds copies of the neighbours' levels for the faces
+/// looking into it
+pub struct Light<S: ChunkSize = DefaultSize> {
+    /// Sky light in the high nibble, block light in the low one
+    values: Storage<u8>,
+    /// Whether each column, by `[x, z]`, is open to the sky above the chunk
+    sky: Storage<bool>,
+    /// Voxels changed since the last [`Chunk::update_light`]
+    pending: Vec<usize>,
+    add: VecDeque<usize>,
+    remove: VecDeque<(usize, u8)>,
+    /// Voxels whose light changed since last drained
+    pub changes: Vec<usize>,
+    _size: PhantomData<S>,
+}
+
+impl<S: ChunkSize> Light<S> {
+    fn new(sky: impl Fn([u32; 2]) -> bool) -> Self {
+        let mut values = Storage::uniform(0, S::VOL);
+        let sky = Storage::from_fn(S::AREA, |i| sky(i.yz::<S>()));
+
+        // top padding of open columns is the sky
+        for z in 0..S::LEN_U32 {
+            for x in 0..S::LEN_U32 {
+                if sky[[x, z].i_2d::<S>()] {
+                    values.set(
+                        [x, S::LEN_U32 - 1, z].i_3d::<S>(),
+                        MAX_LIGHT << Channel::Sky.shift(),
+                    );
+                }
+            }
+        }
+
+        Self {
+            values,
+            sky,
+            pending: Vec::new(),
+            add: VecDeque::new(),
+            remove: VecDeque::new(),
+            changes: Vec::new(),
+            _size: PhantomData,
+        }
+    }
+
+    /// Copy of the levels without anything queued
+    pub fn snapshot(&self) -> Self {
+        Self {
+            values: self.values.clone(),
+            sky: self.sky.clone(),
+            pending: Vec::new(),
+            add: VecDeque::new(),
+            remove: VecDeque::new(),
+            changes: Vec::new(),
+            _size: PhantomData,
+        }
+    }
+
+    /// Copy where every voxel takes the brightest level of its block of `size`³ voxels, per
+    /// channel
+    pub fn collapsed(&self, size: u32) -> Self {
+        let mut collapsed = self.snapshot();
+        for block in blocks::<S>(size) {
+            self.collapse_block(&mut collapsed, block);
+        }
+        collapsed
+    }
+
+    /// Sets every voxel of `block` in `collapsed` to the brightest level of the block here, per
+    /// channel
+    pub fn collapse_block(&self, collapsed: &mut Self, block: [Range<u32>; 3]) {
+        let mut value = 0;
+        for channel in Channel::ALL {
+            let brightest = block_indices::<S>(block.clone())
+                .map(|i| self.get(i, channel))
+                .max()
+                .unwrap_or(0);
+            value |= brightest << channel.shift();
+        }
+
+        for i in block_indices::<S>(block) {
+            collapsed.values.set(i, value);
+        }
+    }
+
+    /// Copies the levels of the padding over, which blocks never collapse
+    pub fn copy_padding(&self, to: &mut Self) {
+        let last = S::LEN_U32 - 1;
+        for z in 0..S::LEN_U32 {
+            for y in 0..S::LEN_U32 {
+                let edge = z == 0 || z == last || y == 0 || y == last;
+                let step = if edge { 1 } else { last as usize };
+                for x in (0..S::LEN_U32).step_by(step) {
+                    let i = [x, y, z].i_3d::<S>();
+                    to.values.set(i, self.values[i]);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, p: impl Index3d, channel: Channel) -> u8 {
+        (self.values[p.i_3d::<S>()] >> channel.shift()) & MAX_LIGHT
+    }
+
+    /// Both channels of `p` as stored, for copying into a neighbour's padding
+    #[inline]
+    pub fn packed(&self, p: impl Index3d) -> u8 {
+        self.values[p.i_3d::<S>()]
+    }
+
+    /// Sets padding voxel `p` to levels [`Light::packed`] from the neighbour it belongs to
+    #[inline]
+    pub fn set_padding(&mut self, p: impl Index3d, packed: u8) {
+        self.values.set(p.i_3d::<S>(), packed);
+    }
+
+    /// Brightest of both channels
+    #[inline]
+    pub fn level(&self, p: impl Index3d) -> u8 {
+        let i = p.i_3d::<S>();
+        self.get(i, Channel::Sky).max(self.get(i, Channel::Block))
+    }
+
+    #[inline]
+    fn set(&mut self, i: usize, channel: Channel, level: u8) {
+        let shift = channel.shift();
+        self.values.update(i, |value| {
+            (value & !(MAX_LIGHT << shift)) | (level << shift)
+        });
+        self.changes.push(i);
+    }
+
+    /// Queues `p` to be relit on the next [`Chunk::update_light`]
+    #[inline]
+    pub fn invalidate(&mut self, p: impl Index3d) {
+        self.pending.push(p.i_3d::<S>());
+    }
+
+    /// Light voxel `i` gives off regardless of its neighbours
+    #[inline]
+    fn source(&self, masks: &Masks<S>, voxels: &Voxels, i: usize, channel: Channel) -> u8 {
+        match channel {
+            Channel::Sky => {
+                let [x, y, z] = i.xyz::<S>();
+                match cost(masks, i) {
+                    Some(cost) if y == S::LEN_U32 - 2 && self.sky[[x, z].i_2d::<S>()] => {
+                        MAX_LIGHT + 1 - cost
+                    }
+                    _ => 0,
+                }
+            }
+            Channel::Block => emission(voxels[i]),
+        }
+    }
+
+    /// Lights the padding below the top as if the light went on into it, until the neighbours'
+    /// levels are copied in. Only the sky falling straight down and light from the nearest
+    /// unpadded voxel reach it
+    fn light_padding(&mut self, masks: &Masks<S>) {
+        let last = S::LEN_U32 - 1;
+
+        // top down so the sky has fallen through the voxels above
+        for y in (0..last).rev() {
+            for z in 0..=last {
+                let edge = z == 0 || z == last || y == 0;
+                let step = if edge { 1 } else { last as usize };
+                for x in (0..=last).step_by(step) {
+                    self.light_padding_voxel(masks, [x, y, z].i_3d::<S>());
+                }
+            }
+        }
+    }
+
+    /// Lights padding voxel `i`, see [`Light::light_padding`]
+    fn light_padding_voxel(&mut self, masks: &Masks<S>, i: usize) {
+        let Some(cost) = cost(masks, i) else {
+            return;
+        };
+        let nearest = nearest_unpadded::<S>(i);
+
+        for channel in Channel::ALL {
+            let falling = channel == Channel::Sky
+                && cost == 1
+                && self.get(i + Shape3d::<S>::STRIDE_Y, channel) == MAX_LIGHT;
+            let level = if falling {
+                MAX_LIGHT
+            } else {
+                self.get(nearest, channel).saturating_sub(cost)
+            };
+            self.set(i, channel, level);
+        }
+    }
+
+    fn propagate(&mut self, masks: &Masks<S>, channel: Channel) {
+        while let Some(i) = self.add.pop_front() {
+            let level = self.get(i, channel);
+
+            for (n, down) in neighbours::<S>(i) {
+                let Some(cost) = cost(masks, n) else {
+                    continue;
+                };
+
+                let next = if channel == Channel::Sky && down && level == MAX_LIGHT && cost == 1 {
+                    MAX_LIGHT
+                } else {
+                    level.saturating_sub(cost)
+                };
+
+                if next > self.get(n, channel) {
+                    self.set(n, channel, next);
+                    self.add.push_back(n);
+                }
+            }
+        }
+    }
+
+    /// Darkens everything lit through the voxels in `remove`, queueing the light at the edge of
+    /// the darkened region to flood back in
+    fn unpropagate(&mut self, masks: &Masks<S>, voxels: &Voxels, channel: Channel) {
+        while let Some((i, level)) = self.remove.pop_front() {
+            for (n, down) in neighbours::<S>(i) {
+                let n_level = self.get(n, channel);
+                if n_level == 0 {
+                    continue;
+                }
+
+                let lit_by_i = n_level < level
+                    || (channel == Channel::Sky
+                        && down
+                        && level == MAX_LIGHT
+                        && n_level == MAX_LIGHT);
+
+                if !lit_by_i {
+                    self.add.push_back(n);
+                    continue;
+                }
+
+                let source = self.source(masks, voxels, n, channel);
+                self.set(n, channel, source);
+                self.remove.push_back((n, n_level));
+                if source > 0 {
+                    self.add.push_back(n);
+                }
+            }
+        }
+    }
+}
+
+impl<S: ChunkSize> Chunk<S> {
+    /// Lights the chunk from scratch, `sky` telling which columns, by `[x, z]`, are open to the
+    /// sky above it
+    pub fn enable_light(&mut self, sky: impl Fn([u32; 2]) -> bool) {
+        let mut light = Light::new(sky);
+
+        // uniform chunks whose light is known to be uniform too keep a single level
+        if let Storage::Uniform { value, .. } = self.voxels {
+            let uniform = match value {
+                Some(Voxel::Solid) => Some(0),
+                None if matches!(light.sky, Storage::Uniform { value: true, .. }) => {
+                    Some(MAX_LIGHT << Channel::Sky.shift())
+                }
+                _ => None,
+            };
+            if let Some(uniform) = uniform {
+                light.values = Storage::uniform(uniform, S::VOL);
+                self.light = Some(Box::new(light));
+                return;
+            }
+        }
+
+        for channel in Channel::ALL {
+            for z in 1..S::LEN_U32 - 1 {
+                for y in 1..S::LEN_U32 - 1 {
+                    for x in 1..S::LEN_U32 - 1 {
+                        let i = [x, y, z].i_3d::<S>();
+                        let source = light.source(&self.masks, &self.voxels, i, channel);
+                        if source > 0 {
+                            light.set(i, channel, source);
+                            light.add.push_back(i);
+                        }
+                    }
+                }
+            }
+
+            light.propagate(&self.masks, channel);
+        }
+        light.light_padding(&self.masks);
+
+        light.changes.clear();
+        self.light = Some(Box::new(light));
+    }
+
+    /// Queues `p` to be relit if lighting is enabled
+    #[inline]
+    pub fn invalidate_light(&mut self, p: impl Index3d) {
+        if let Some(light) = &mut self.light {
+            light.invalidate(p);
+        }
+    }
+
+    /// Relights around every voxel invalidated since the last update.
+    ///
+    /// Reads the front masks so call this outside of [`Chunk::liquid_tick`]
+    pub fn update_light(&mut self) {
+        let Some(light) = &mut self.light else {
+            return;
+        };
+        if light.pending.is_empty() {
+            return;
+        }
+
+        let mut pending = std::mem::take(&mut light.pending);
+        pending.sort_unstable();
+        pending.dedup();
+
+        for channel in Channel::ALL {
+            for &i in &pending {
+                let level = light.get(i, channel);
+                light.set(i, channel, 0);
+                light.remove.push_back((i, level));
+
+                // whatever is there now may let light in from any side
+                light.add.extend(neighbours::<S>(i).map(|(n, _)| n));
+            }
+
+            light.unpropagate(&self.masks, &self.voxels, channel);
+
+            for &i in &pending {
+                let source = light.source(&self.masks, &self.voxels, i, channel);
+                if source > light.get(i, channel) {
+                    light.set(i, channel, source);
+                    light.add.push_back(i);
+                }
+            }
+
+            light.propagate(&self.masks, channel);
+        }
+
+        // the padding next to the border follows it
+        let mut border = light.changes.clone();
+        border.sort_unstable();
+        border.dedup();
+        for i in border {
+            for p in padding_around::<S>(i) {
+                light.light_padding_voxel(&self.masks, p);
+            }
+        }
+
+        pending.clear();
+        light.pending = pending;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::size::Size32;
+
+    #[test]
+    fn uniform_chunks_keep_uniform_light() {
+        let mut solid = Chunk::<Size32>::uniform(Some(Voxel::Solid));
+        solid.enable_light(|_| true);
+        let light = solid.light.unwrap();
+        assert!(matches!(light.values, Storage::Uniform { value: 0, .. }));
+
+        let mut air = Chunk::<Size32>::uniform(None);
+        air.enable_light(|_| true);
+        let light = air.light.unwrap();
+        assert!(matches!(light.values, Storage::Uniform { .. }));
+        assert_eq!(light.get([3, 1, 3], Channel::Sky), MAX_LIGHT);
+    }
+
+    #[test]
+    fn open_air_lights_like_any_other_chunk() {
+        // a single solid voxel in the padding keeps the voxels from being uniform
+        let mut air = Chunk::<Size32>::uniform(None);
+        air.set([0, 0, 0], Some(Voxel::Solid));
+        air.enable_light(|_| true);
+        let light = air.light.unwrap();
+
+        for p in [[1, 1, 1], [5, 10, 20], [30, 30, 30]] {
+            assert_eq!(light.get(p, Channel::Sky), MAX_LIGHT, "{p:?}");
+            assert_eq!(light.get(p, Channel::Block), 0, "{p:?}");
+        }
+    }
+
+    /// Asserts `chunk` is lit the same as lighting its voxels from scratch
+    fn assert_lit_from_scratch(chunk: &Chunk<Size32>, what: &str) {
+        let mut fresh = Chunk::<Size32>::from_voxels(chunk.voxels.clone());
+        fresh.enable_light(|_| true);
+
+        let (light, fresh) = (chunk.light.as_ref().unwrap(), fresh.light.unwrap());
+        for i in 0..Size32::VOL {
+            for channel in Channel::ALL {
+                assert_eq!(
+                    light.get(i, channel),
+                    fresh.get(i, channel),
+                    "{channel:?} at {:?} after {what}",
+                    i.xyz::<Size32>()
+                );
+            }
+        }
+    }
+
+    /// Open sky over a floor 4 voxels deep
+    fn floor() -> Chunk<Size32> {
+        let mut chunk = Chunk::<Size32>::from_fn(|[_, y, _]| (y < 4).then_some(Voxel::Solid));
+        chunk.enable_light(|_| true);
+        chunk
+    }
+
+    #[test]
+    fn relights_placed_and_removed_solid() {
+        let mut chunk = floor();
+
+        chunk.set([10, 20, 10], Some(Voxel::Solid));
+        chunk.update_light();
+        assert_lit_from_scratch(&chunk, "placing a solid");
+        assert!(
+            chunk
+                .light
+                .as_ref()
+                .unwrap()
+                .get([10, 19, 10], Channel::Sky)
+                < MAX_LIGHT
+        );
+
+        chunk.set([10, 20, 10], None);
+        chunk.update_light();
+        assert_lit_from_scratch(&chunk, "removing it");
+    }
+
+    #[test]
+    fn relights_placed_and_removed_lava() {
+        let mut chunk = floor();
+
+        chunk.set([10, 4, 10], Some(Voxel::Lava));
+        chunk.update_light();
+        assert_lit_from_scratch(&chunk, "placing lava");
+        assert!(
+            chunk
+                .light
+                .as_ref()
+                .unwrap()
+                .get([12, 4, 10], Channel::Block)
+                > 0
+        );
+
+        chunk.set([10, 4, 10], None);
+        chunk.update_light();
+        assert_lit_from_scratch(&chunk, "removing it");
+    }
+}
@@ -1,26 +1,27 @@
 mod action;
 
 use bevy::platform::hash::FixedState;
-use bit_iter::BitIter;
 use std::hash::BuildHasher;
 
 use action::{ACTIONS, Action, DOWN_ACTION};
 
 use super::index::{Index2d, Index3d};
-use super::{Chunk, LEN_U32, PAD_MASK};
+use super::masks::write_bits;
+use super::{Chunk, ChunkSize, Row, Voxel};
 
-impl Chunk {
+impl<S: ChunkSize> Chunk<S> {
     pub fn liquid_tick(&mut self, tick: u64) {
         let state = FixedState::with_seed(tick);
         let inv_state = FixedState::with_seed(!tick);
+        self.border_moves.clear();
 
-        for z in 1..LEN_U32 - 1 {
-            'row: for y in 1..LEN_U32 - 1 {
-                let i_2d = [y, z].i_2d();
+        for z in 1..S::LEN_U32 - 1 {
+            'row: for y in 1..S::LEN_U32 - 1 {
+                let i_2d = [y, z].i_2d::<S>();
 
-                let mut liquid = self.masks.dblt_masks.front.liquid_mask[i_2d] & !PAD_MASK;
+                let mut liquid = self.masks.dblt_masks.front.liquid_mask[i_2d] & !S::Row::PAD;
 
-                if liquid == 0 {
+                if liquid == S::Row::ZERO {
                     continue 'row;
                 }
 
@@ -29,13 +30,13 @@ impl Chunk {
 
                     liquid &= !moved;
 
-                    if liquid == 0 {
+                    if liquid == S::Row::ZERO {
                         continue 'row;
                     }
                 }
 
-                let x_mask = state.hash_one(i_2d);
-                let pos_mask = inv_state.hash_one(i_2d);
+                let x_mask = S::Row::truncate(state.hash_one(i_2d));
+                let pos_mask = S::Row::truncate(inv_state.hash_one(i_2d));
 
                 let group_masks = [
                     x_mask & pos_mask,
@@ -48,7 +49,7 @@ impl Chunk {
                     for i in 0..4 {
                         for j in 0..4 {
                             let group = liquid & group_masks[(i + j) % 4];
-                            if group == 0 {
+                            if group == S::Row::ZERO {
                                 continue;
                             }
 
@@ -56,7 +57,7 @@ impl Chunk {
 
                             liquid &= !moved;
 
-                            if liquid == 0 {
+                            if liquid == S::Row::ZERO {
                                 continue 'row;
                             }
                         }
@@ -68,19 +69,19 @@ impl Chunk {
 
     fn try_move_row(
         &mut self,
-        group: u64,
+        group: S::Row,
         src_i_2d: usize,
         state: &FixedState,
         action: &Action,
-    ) -> u64 {
+    ) -> S::Row {
         let (delta, prereqs) = action;
 
-        let (d_x, d_i_2d) = delta.x_and_i_2d();
-        let d_i_3d = delta.i_3d();
+        let (d_x, d_i_2d) = delta.x_and_i_2d::<S>();
+        let d_i_3d = delta.i_3d::<S>();
 
-        let mut prereq_mask = !0;
+        let mut prereq_mask = !S::Row::ZERO;
         for prereq in *prereqs {
-            let (x, i_2d) = prereq.delta.x_and_i_2d();
+            let (x, i_2d) = prereq.delta.x_and_i_2d::<S>();
             let i_2d = src_i_2d.wrapping_add_signed(i_2d);
             let mask = self.masks.dblt_masks.front.some_mask[i_2d].inv_shift(x);
 
@@ -89,84 +90,112 @@ impl Chunk {
 
         let dst_i_2d = src_i_2d.wrapping_add_signed(d_i_2d);
 
-        let try_move =
-            group & prereq_mask & !self.masks.dblt_masks.front.some_mask[dst_i_2d].inv_shift(d_x);
+        // the padding belongs to a neighbour, moves into it are left to whoever sees both
+        let [dst_y, dst_z] = dst_i_2d.yz::<S>();
+        let dst_padding = if [dst_y, dst_z]
+            .iter()
+            .any(|&c| c == 0 || c == S::LEN_U32 - 1)
+        {
+            !S::Row::ZERO
+        } else {
+            S::Row::PAD
+        };
+        let dst_empty = !self.masks.dblt_masks.front.some_mask[dst_i_2d];
+
+        let into_padding = group & prereq_mask & (dst_empty & dst_padding).inv_shift(d_x);
+        for x in into_padding.ones() {
+            let src_i_3d = (x, src_i_2d).i_3d::<S>();
+            let dst_i_3d = src_i_3d.wrapping_add_signed(d_i_3d);
+            self.border_moves.push((src_i_3d, dst_i_3d));
+        }
+
+        let try_move = group & prereq_mask & (dst_empty & !dst_padding).inv_shift(d_x);
 
         let success = try_move & !self.masks.dblt_masks.back.some_mask[dst_i_2d].inv_shift(d_x);
         let failure = try_move & !success;
 
-        let mut moved = success;
+        let mut moved = success | into_padding;
 
-        if success != 0 {
+        if success != S::Row::ZERO {
             let add_mask = success.shift(d_x);
 
             self.masks.dblt_masks.back.some_mask[dst_i_2d] |= add_mask;
             self.masks.dblt_masks.back.liquid_mask[dst_i_2d] |= add_mask;
-            self.masks.transparent_mask[dst_i_2d] |= add_mask; // assuming liquid is always transparent
 
             self.masks.dblt_masks.back.some_mask[src_i_2d] &= !success;
             self.masks.dblt_masks.back.liquid_mask[src_i_2d] &= !success;
-            self.masks.transparent_mask[dst_i_2d] &= !success;
         }
 
-        for x in BitIter::from(success) {
-            let src_i_3d = (x, src_i_2d).i_3d();
+        for x in success.ones() {
+            let src_i_3d = (x, src_i_2d).i_3d::<S>();
             let dst_i_3d = src_i_3d.wrapping_add_signed(d_i_3d);
 
-            self.voxels[dst_i_3d] = self.voxels[src_i_3d];
-            self.voxels[src_i_3d] = None;
+            self.move_voxel(src_i_3d, dst_i_3d);
 
             self.dst_to_src.insert(dst_i_3d, src_i_3d);
         }
 
-        for x in BitIter::from(failure) {
-            let src_i_3d = (x, src_i_2d).i_3d();
+        for x in failure.ones() {
+            let src_i_3d = (x, src_i_2d).i_3d::<S>();
             let dst_i_3d = src_i_3d.wrapping_add_signed(d_i_3d);
 
-            let other_src_i_3d = self.dst_to_src.get_mut(&dst_i_3d).unwrap();
+            let other_src_i_3d = self.dst_to_src[&dst_i_3d];
 
             let priority = state.hash_one(src_i_3d);
-            let other_priority = state.hash_one(*other_src_i_3d);
+            let other_priority = state.hash_one(other_src_i_3d);
 
             if priority >= other_priority {
-                let src_bit = 1 << x;
+                let src_bit = S::Row::ONE << x as u32;
                 moved |= src_bit;
 
-                let (other_x, other_i_2d) = other_src_i_3d.x_and_i_2d();
-                let other_bit = 1 << other_x;
+                let (other_x, other_i_2d) = other_src_i_3d.x_and_i_2d::<S>();
+                let other_bit = S::Row::ONE << other_x;
 
                 self.masks.dblt_masks.back.liquid_mask[other_i_2d] |= other_bit;
                 self.masks.dblt_masks.back.some_mask[other_i_2d] |= other_bit;
-                self.masks.transparent_mask[other_i_2d] |= other_bit; // same assumption
                 self.masks.dblt_masks.back.liquid_mask[src_i_2d] &= !src_bit;
                 self.masks.dblt_masks.back.some_mask[src_i_2d] &= !src_bit;
-                self.masks.transparent_mask[other_i_2d] &= !src_bit;
 
-                self.voxels[*other_src_i_3d] = self.voxels[src_i_3d];
-                self.voxels[src_i_3d] = None;
+                // send the other voxel home before taking its place
+                self.move_voxel(dst_i_3d, other_src_i_3d);
+                self.move_voxel(src_i_3d, dst_i_3d);
 
-                *other_src_i_3d = src_i_3d;
+                self.dst_to_src.insert(dst_i_3d, src_i_3d);
             }
         }
 
         moved
     }
+
+    /// Swaps the voxels (and temperatures) at `src` and `dst`, keeping `transparent_mask` in sync
+    fn move_voxel(&mut self, src_i_3d: usize, dst_i_3d: usize) {
+        self.voxels.swap(src_i_3d, dst_i_3d);
+
+        for i_3d in [src_i_3d, dst_i_3d] {
+            let (x, i_2d) = i_3d.x_and_i_2d::<S>();
+            let transparent = self.voxels[i_3d].is_some_and(Voxel::is_transparent);
+            let bit = S::Row::ONE << x;
+            self.masks
+                .transparent_mask
+                .update(i_2d, |row| write_bits(row, bit, transparent));
+        }
+
+        if let Some(temperature) = &mut self.temperature {
+            temperature.swap(src_i_3d, dst_i_3d);
+        }
+    }
 }
 
 trait Shift: Copy {
-    const ONE: Self;
-    
     /// shl
-    fn shift(self, rhs: isize) -> u64;
+    fn shift(self, rhs: isize) -> Self;
 
     /// shr
-    fn inv_shift(self, rhs: isize) -> u64;
+    fn inv_shift(self, rhs: isize) -> Self;
 }
 
-impl Shift for u64 {
-    const ONE: Self = 1;
-
-    fn shift(self, rhs: isize) -> u64 {
+impl<R: Row> Shift for R {
+    fn shift(self, rhs: isize) -> Self {
         let mut out = self.wrapping_shr(-rhs as u32);
         if rhs > 0 {
             out = self.wrapping_shl(rhs as u32)
@@ -174,7 +203,7 @@ impl Shift for u64 {
         out
     }
 
-    fn inv_shift(self, rhs: isize) -> u64 {
+    fn inv_shift(self, rhs: isize) -> Self {
         let mut out = self.wrapping_shl(-rhs as u32);
         if rhs > 0 {
             out = self.wrapping_shr(rhs as u32)
@@ -182,3 +211,58 @@ impl Shift for u64 {
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Size64;
+    use crate::chunk::size::Size32;
+
+    /// Ticks a single liquid voxel resting on nothing and checks it fell one voxel
+    fn liquid_falls<S: ChunkSize>() {
+        let mut chunk = Chunk::<S>::default();
+        chunk.set([5, 10, 5], Some(Voxel::Liquid));
+
+        chunk.liquid_tick(0);
+        chunk.masks.dblt_masks.copy_back_to_front();
+
+        assert_eq!(chunk.voxels[[5, 9, 5].i_3d::<S>()], Some(Voxel::Liquid));
+        assert_eq!(chunk.voxels[[5, 10, 5].i_3d::<S>()], None);
+        assert!(chunk.masks.is_liquid([5, 9, 5]));
+        assert!(!chunk.masks.is_liquid([5, 10, 5]));
+        assert_eq!(
+            chunk.dst_to_src[&[5, 9, 5].i_3d::<S>()],
+            [5, 10, 5].i_3d::<S>()
+        );
+    }
+
+    #[test]
+    fn leaves_moves_into_the_padding_to_the_neighbour() {
+        let mut chunk = Chunk::<Size32>::default();
+        chunk.set([5, 1, 5], Some(Voxel::Liquid));
+
+        chunk.liquid_tick(0);
+        chunk.masks.dblt_masks.copy_back_to_front();
+
+        assert_eq!(
+            chunk.voxels[[5, 1, 5].i_3d::<Size32>()],
+            Some(Voxel::Liquid)
+        );
+        assert!(chunk.masks.is_liquid([5, 1, 5]));
+        assert!(!chunk.masks.is_some([5, 0, 5]));
+        assert_eq!(
+            chunk.border_moves,
+            [([5, 1, 5].i_3d::<Size32>(), [5, 0, 5].i_3d::<Size32>())]
+        );
+    }
+
+    #[test]
+    fn ticks_size32() {
+        liquid_falls::<Size32>();
+    }
+
+    #[test]
+    fn ticks_size64() {
+        liquid_falls::<Size64>();
+    }
+}
@@ -1,12 +1,6 @@
+use super::super::ChunkSize;
 use super::super::index::*;
 
-const I_STRIDE_Y_2D: isize = STRIDE_Y_2D as isize;
-const I_STRIDE_Z_2D: isize = STRIDE_Z_2D as isize;
-
-const I_STRIDE_X_3D: isize = STRIDE_X_3D as isize;
-const I_STRIDE_Y_3D: isize = STRIDE_Y_3D as isize;
-const I_STRIDE_Z_3D: isize = STRIDE_Z_3D as isize;
-
 pub type Action = (Delta, &'static [PreReq]);
 type ActionGroup = [Action; 4];
 
@@ -14,15 +8,20 @@ pub struct Delta([isize; 3]);
 
 impl Delta {
     #[inline]
-    pub fn i_3d(&self) -> isize {
+    pub fn i_3d<S: ChunkSize>(&self) -> isize {
         let [x, y, z] = self.0;
-        x * I_STRIDE_X_3D + y * I_STRIDE_Y_3D + z * I_STRIDE_Z_3D
+        x * Shape3d::<S>::STRIDE_X as isize
+            + y * Shape3d::<S>::STRIDE_Y as isize
+            + z * Shape3d::<S>::STRIDE_Z as isize
     }
 
     #[inline]
-    pub fn x_and_i_2d(&self) -> (isize, isize) {
+    pub fn x_and_i_2d<S: ChunkSize>(&self) -> (isize, isize) {
         let [x, y, z] = self.0;
-        (x, y * I_STRIDE_Y_2D + z * I_STRIDE_Z_2D)
+        (
+            x,
+            y * Shape2d::<S>::STRIDE_Y as isize + z * Shape2d::<S>::STRIDE_Z as isize,
+        )
     }
 }
 
@@ -0,0 +1,108 @@
+use bevy::ecs::lifecycle::HookContext;
+use bevy::ecs::system::SystemParam;
+use bevy::ecs::world::DeferredWorld;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+use super::{BoxChunk, Chunk, ChunkSize, DefaultSize};
+
+/// Voxels per chunk along each axis, not counting padding
+pub const INNER: i32 = DefaultSize::LEN as i32 - 2;
+
+/// Which chunk of the world this is. Voxel `p` of the chunk is at `origin() + p` in world space
+#[derive(Component, Deref, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+#[component(immutable, on_insert = on_insert, on_replace = on_replace)]
+pub struct ChunkPos(pub IVec3);
+
+impl ChunkPos {
+    #[inline]
+    pub fn origin(self) -> IVec3 {
+        self.0 * INNER
+    }
+
+    /// The chunk whose unpadded region holds world voxel `p` and `p` local to it
+    #[inline]
+    pub fn containing(p: IVec3) -> (Self, UVec3) {
+        let shifted = p - IVec3::ONE;
+        let pos = shifted.div_euclid(IVec3::splat(INNER));
+        let local = shifted.rem_euclid(IVec3::splat(INNER)) + IVec3::ONE;
+        (Self(pos), local.as_uvec3())
+    }
+
+    /// Every chunk whose padded region holds world voxel `p` and `p` local to each, the one
+    /// containing it first
+    pub fn overlapping(p: IVec3) -> impl Iterator<Item = (Self, UVec3)> {
+        let (pos, local) = Self::containing(p);
+        let local = local.as_ivec3();
+        let offsets = || [0, -1, 1].into_iter();
+
+        offsets()
+            .flat_map(move |z| offsets().flat_map(move |y| offsets().map(move |x| ivec3(x, y, z))))
+            .filter_map(move |d| {
+                let p = local - d * INNER;
+                let in_bounds =
+                    p.cmpge(IVec3::ZERO).all() && p.cmplt(IVec3::splat(INNER + 2)).all();
+                in_bounds.then(|| (Self(*pos + d), p.as_uvec3()))
+            })
+    }
+}
+
+#[derive(Resource, Default, Deref)]
+pub struct ChunkMap(HashMap<IVec3, Entity>);
+
+fn on_insert(mut world: DeferredWorld, ctx: HookContext) {
+    let pos = *world.get::<ChunkPos>(ctx.entity).unwrap();
+    world.resource_mut::<ChunkMap>().0.insert(*pos, ctx.entity);
+}
+
+fn on_replace(mut world: DeferredWorld, ctx: HookContext) {
+    let pos = *world.get::<ChunkPos>(ctx.entity).unwrap();
+    world.resource_mut::<ChunkMap>().0.remove(&*pos);
+}
+
+/// Read access to voxels by world position regardless of which chunk they're in
+#[derive(SystemParam)]
+pub struct Chunks<'w, 's> {
+    map: Res<'w, ChunkMap>,
+    chunks: Query<'w, 's, &'static BoxChunk>,
+}
+
+impl Chunks<'_, '_> {
+    /// The chunk holding world voxel `p` and `p` local to it
+    #[inline]
+    pub fn get(&self, p: IVec3) -> Option<(&Chunk, UVec3)> {
+        let (pos, local) = ChunkPos::containing(p);
+        let entity = self.map.get(&*pos)?;
+        let chunk = self.chunks.get(*entity).ok()?;
+        Some((chunk, local))
+    }
+
+    /// Average flow of the liquid voxels around `pos` in voxels per tick
+    pub fn flow_at(&self, pos: Vec3) -> Vec3 {
+        let center = pos.floor().as_ivec3();
+
+        let mut sum = Vec3::ZERO;
+        let mut count = 0;
+
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let Some((chunk, p)) = self.get(center + ivec3(x, y, z)) else {
+                        continue;
+                    };
+
+                    if chunk.masks.is_liquid(p) {
+                        sum += chunk.flow.get(p);
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        if count == 0 {
+            Vec3::ZERO
+        } else {
+            sum / count as f32
+        }
+    }
+}
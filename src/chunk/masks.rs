@@ -1,141 +1,125 @@
 // TODO: each row should be a structure for cache friendly writes (reads we're never that cache friendly)
 
-use bevy::prelude::*;
-
 use super::double_buffered::DoubleBuffered;
-use super::index::{Index2d, Index3d};
-use super::{DEFAULT_MASK, Mask, Voxel};
+use super::index::Index3d;
+use super::storage::Storage;
+use super::{ChunkSize, DefaultSize, Mask, Row, Voxel, Voxels, uniform_mask};
 
-pub const PAD_MASK: u64 = (1 << 63) | 1;
+#[derive(Clone)]
+pub struct LiquidTickMasks<S: ChunkSize = DefaultSize> {
+    pub some_mask: Mask<S>,
+    pub liquid_mask: Mask<S>,
+}
 
 #[derive(Clone)]
-pub struct LiquidTickMasks {
-    pub some_mask: Mask,
-    pub liquid_mask: Mask,
+pub struct Masks<S: ChunkSize = DefaultSize> {
+    pub dblt_masks: DoubleBuffered<LiquidTickMasks<S>>,
+    pub transparent_mask: Mask<S>,
 }
 
-impl Default for LiquidTickMasks {
-    fn default() -> Self {
+impl<S: ChunkSize> Masks<S> {
+    /// Masks of a chunk of nothing but `v`
+    pub fn uniform(v: Option<Voxel>) -> Self {
+        let row = |set: bool| uniform_mask::<S>(if set { !S::Row::ZERO } else { S::Row::ZERO });
+        let lt_masks = LiquidTickMasks {
+            some_mask: row(v.is_some()),
+            liquid_mask: row(v.is_some_and(Voxel::is_liquid)),
+        };
+
         Self {
-            some_mask: DEFAULT_MASK,
-            liquid_mask: DEFAULT_MASK,
+            dblt_masks: DoubleBuffered {
+                front: lt_masks.clone(),
+                back: lt_masks,
+            },
+            transparent_mask: row(v.is_some_and(Voxel::is_transparent)),
         }
     }
-}
 
-pub struct Masks {
-    pub dblt_masks: DoubleBuffered<LiquidTickMasks>,
-    pub transparent_mask: Mask,
-}
+    /// Masks of `voxels`, built a row at a time
+    pub fn from_voxels(voxels: &Voxels) -> Self {
+        let mask = |f: fn(Voxel) -> bool| {
+            Storage::from_fn(S::AREA, |i_2d| {
+                (0..S::LEN_U32)
+                    .filter(|&x| voxels[(x, i_2d).i_3d::<S>()].is_some_and(f))
+                    .fold(S::Row::ZERO, |row, x| row | S::Row::ONE << x)
+            })
+        };
+        let lt_masks = LiquidTickMasks {
+            some_mask: mask(|_| true),
+            liquid_mask: mask(Voxel::is_liquid),
+        };
 
-impl Default for Masks {
-    fn default() -> Self {
         Self {
-            dblt_masks: default(),
-            transparent_mask: DEFAULT_MASK,
+            dblt_masks: DoubleBuffered {
+                front: lt_masks.clone(),
+                back: lt_masks,
+            },
+            transparent_mask: mask(Voxel::is_transparent),
         }
     }
-}
 
-impl Masks {
     #[inline]
-    pub fn some_mask(&self) -> &Mask {
+    pub fn some_mask(&self) -> &Mask<S> {
         &self.dblt_masks.front.some_mask
     }
 
     #[inline]
-    pub fn set(&mut self, p: impl Index3d, v: Option<Voxel>) {
-        let (x, i_2d) = p.x_and_i_2d();
-        let bit = 1 << x;
-
-        match v {
-            Some(Voxel::Liquid) => {
-                self.dblt_masks.for_each(|lt_masks| {
-                    lt_masks.some_mask[i_2d] |= bit;
-                    lt_masks.liquid_mask[i_2d] |= bit;
-                });
-                self.transparent_mask[i_2d] |= bit;
-            }
-            Some(_) => {
-                self.dblt_masks.for_each(|lt_masks| {
-                    lt_masks.some_mask[i_2d] |= bit;
-                    lt_masks.liquid_mask[i_2d] &= !bit;
-                });
-                self.transparent_mask[i_2d] &= !bit;
-            }
-            None => {
-                self.dblt_masks.for_each(|lt_masks| {
-                    lt_masks.some_mask[i_2d] &= !bit;
-                    lt_masks.liquid_mask[i_2d] &= !bit;
-                });
-                self.transparent_mask[i_2d] &= !bit;
-            }
-        }
+    pub fn liquid_mask(&self) -> &Mask<S> {
+        &self.dblt_masks.front.liquid_mask
     }
 
     #[inline]
-    pub fn fill_row(&mut self, p: impl Index2d, v: Option<Voxel>) {
-        let i_2d = p.i_2d();
-
-        match v {
-            Some(Voxel::Liquid) => {
-                self.dblt_masks.for_each(|lt_masks| {
-                    lt_masks.some_mask[i_2d] = !0;
-                    lt_masks.liquid_mask[i_2d] = !0;
-                });
-                self.transparent_mask[i_2d] = !0;
-            }
-            Some(_) => {
-                self.dblt_masks.for_each(|lt_masks| {
-                    lt_masks.some_mask[i_2d] = !0;
-                    lt_masks.liquid_mask[i_2d] = 0;
-                });
-                self.transparent_mask[i_2d] = 0;
-            }
-            None => {
-                self.dblt_masks.for_each(|lt_masks| {
-                    lt_masks.some_mask[i_2d] = 0;
-                    lt_masks.liquid_mask[i_2d] = 0;
-                });
-                self.transparent_mask[i_2d] = 0;
-            }
-        }
+    pub fn set(&mut self, p: impl Index3d, v: Option<Voxel>) {
+        let (x, i_2d) = p.x_and_i_2d::<S>();
+        self.write(i_2d, S::Row::ONE << x, v);
     }
 
+    /// Writes `v` into every bit of `bits` in row `i_2d` of all masks
     #[inline]
-    pub fn set_row_padding(&mut self, p: impl Index2d, v: Option<Voxel>) {
-        let i_2d = p.i_2d();
-
-        match v {
-            Some(Voxel::Liquid) => {
-                self.dblt_masks.for_each(|lt_masks| {
-                    lt_masks.some_mask[i_2d] |= PAD_MASK;
-                    lt_masks.liquid_mask[i_2d] |= PAD_MASK;
-                });
-                self.transparent_mask[i_2d] |= PAD_MASK;
-            }
-            Some(_) => {
-                self.dblt_masks.for_each(|lt_masks| {
-                    lt_masks.some_mask[i_2d] |= PAD_MASK;
-                    lt_masks.liquid_mask[i_2d] &= !PAD_MASK;
-                });
-                self.transparent_mask[i_2d] &= !PAD_MASK;
-            }
-            None => {
-                self.dblt_masks.for_each(|lt_masks| {
-                    lt_masks.some_mask[i_2d] &= !PAD_MASK;
-                    lt_masks.liquid_mask[i_2d] &= !PAD_MASK;
-                });
-                self.transparent_mask[i_2d] &= !PAD_MASK;
-            }
-        }
+    fn write(&mut self, i_2d: usize, bits: S::Row, v: Option<Voxel>) {
+        let some = v.is_some();
+        let liquid = v.is_some_and(Voxel::is_liquid);
+        let transparent = v.is_some_and(Voxel::is_transparent);
+
+        self.dblt_masks.for_each(|lt_masks| {
+            lt_masks
+                .some_mask
+                .update(i_2d, |row| write_bits(row, bits, some));
+            lt_masks
+                .liquid_mask
+                .update(i_2d, |row| write_bits(row, bits, liquid));
+        });
+        self.transparent_mask
+            .update(i_2d, |row| write_bits(row, bits, transparent));
     }
 
     #[inline]
     pub fn is_some(&self, p: impl Index3d) -> bool {
-        let (x, i_2d) = p.x_and_i_2d();
+        let (x, i_2d) = p.x_and_i_2d::<S>();
+
+        let bit = S::Row::ONE << x;
+        self.dblt_masks.front.some_mask[i_2d] & bit != S::Row::ZERO
+    }
 
-        let bit = 1 << x;
-        self.dblt_masks.front.some_mask[i_2d] & bit != 0
+    #[inline]
+    pub fn is_liquid(&self, p: impl Index3d) -> bool {
+        let (x, i_2d) = p.x_and_i_2d::<S>();
+
+        let bit = S::Row::ONE << x;
+        self.dblt_masks.front.liquid_mask[i_2d] & bit != S::Row::ZERO
     }
+
+    #[inline]
+    pub fn is_solid(&self, p: impl Index3d) -> bool {
+        let (x, i_2d) = p.x_and_i_2d::<S>();
+
+        let bit = S::Row::ONE << x;
+        let front = &self.dblt_masks.front;
+        front.some_mask[i_2d] & !front.liquid_mask[i_2d] & bit != S::Row::ZERO
+    }
+}
+
+#[inline]
+pub fn write_bits<R: Row>(row: R, bits: R, value: bool) -> R {
+    if value { row | bits } else { row & !bits }
 }
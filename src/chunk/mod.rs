@@ -1,140 +1,180 @@
 mod double_buffered;
+pub mod flow;
 pub mod index;
+pub mod light;
 mod liquid_tick;
+pub mod map;
 pub mod masks;
+pub mod palette;
+mod query;
+pub mod save;
+pub mod size;
+pub mod storage;
+pub mod temperature;
 
 use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
 
-use index::Index3d;
+use flow::Flow;
+use index::{Index3d, padding_pairs};
+use light::Light;
 use masks::Masks;
+use palette::Palette;
+use storage::Storage;
+use temperature::Temperature;
 
-pub use masks::PAD_MASK;
+pub use size::{ChunkSize, Row, Size64};
 
-pub const BITS: u32 = 6;
+/// Size of the chunks the world is made of
+pub type DefaultSize = Size64;
 
-pub const LEN: usize = 1 << BITS; // 64
-pub const LEN_U32: u32 = LEN as u32;
-pub const AREA: usize = LEN * LEN;
-pub const VOL: usize = LEN * LEN * LEN;
+/// A bit per voxel, one [`Row`] per row
+pub type Mask<S> = Storage<<S as ChunkSize>::Row>;
+pub type Voxels = Storage<Option<Voxel>, Palette<Option<Voxel>>>;
 
-pub type Mask = [u64; AREA];
-pub type Voxels = [Option<Voxel>; VOL];
-
-pub const DEFAULT_MASK: Mask = [0; AREA];
-pub const DEFAULT_VOXELS: Voxels = [None; VOL];
+#[inline]
+pub fn uniform_mask<S: ChunkSize>(row: S::Row) -> Mask<S> {
+    Storage::uniform(row, S::AREA)
+}
 
 // TODO: runtime enumeration/indexing
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Voxel {
     Liquid,
     Solid,
+    Ice,
+    Lava,
+}
+
+impl Voxel {
+    #[inline]
+    pub fn is_liquid(self) -> bool {
+        matches!(self, Voxel::Liquid | Voxel::Lava)
+    }
+
+    #[inline]
+    pub fn is_transparent(self) -> bool {
+        matches!(self, Voxel::Liquid | Voxel::Ice)
+    }
 }
 
-pub struct Chunk {
+pub struct Chunk<S: ChunkSize = DefaultSize> {
     pub voxels: Voxels,
-    pub masks: Masks,
+    pub masks: Masks<S>,
     pub dst_to_src: HashMap<usize, usize>,
+    /// `(src, dst)` of liquid the last tick moved into the padding, not moved yet since the
+    /// padding belongs to a neighbour
+    pub border_moves: Vec<(usize, usize)>,
+    pub flow: Flow<S>,
+    pub temperature: Option<Box<Temperature<S>>>,
+    pub light: Option<Box<Light<S>>>,
+    /// Whether it changed since it was generated or loaded, so it has to be saved to be kept
+    pub unsaved: bool,
 }
 
-impl Default for Chunk {
+impl<S: ChunkSize> Default for Chunk<S> {
+    /// All air
     fn default() -> Self {
+        Self::uniform(None)
+    }
+}
+
+impl<S: ChunkSize> Chunk<S> {
+    /// Every voxel, padding included, set to `v` without storing any of them apart. Stays that
+    /// way until [`Chunk::set`] writes something else
+    pub fn uniform(v: Option<Voxel>) -> Self {
         Self {
-            voxels: DEFAULT_VOXELS,
-            masks: default(),
+            voxels: Storage::uniform(v, S::VOL),
+            masks: Masks::uniform(v),
             dst_to_src: default(),
+            border_moves: Vec::new(),
+            flow: default(),
+            temperature: None,
+            light: None,
+            unsaved: false,
         }
     }
-}
-
-impl Chunk {
-    pub fn set(&mut self, p: impl Index3d, v: Option<Voxel>) {
-        self.voxels[p.i_3d()] = v;
 
-        self.masks.set(p, v);
+    /// Chunk of `f` at every voxel, padding included, in one pass rather than a
+    /// [`Chunk::set`] each
+    pub fn from_fn(mut f: impl FnMut([u32; 3]) -> Option<Voxel>) -> Self {
+        Self::from_voxels(Storage::from_fn(S::VOL, |i| f(i.xyz::<S>())))
     }
 
-    pub fn fill_padding(&mut self, v: Option<Voxel>) {
-        // +-Z
-        for z in [0, LEN_U32 - 1] {
-            for y in 0..LEN_U32 {
-                self.masks.fill_row([y, z], v);
-                for x in 0..LEN_U32 {
-                    let i = [x, y, z].i_3d();
-                    self.voxels[i] = v;
-                }
-            }
-        }
-
-        // +-Y
-        for z in 1..LEN_U32 - 1 {
-            for y in [0, LEN_U32 - 1] {
-                self.masks.fill_row([y, z], v);
-                for x in 0..LEN_U32 {
-                    let i = [x, y, z].i_3d();
-                    self.voxels[i] = v;
-                }
-            }
-        }
-
-        // +-X
-        for z in 1..LEN_U32 - 1 {
-            for y in 1..LEN_U32 - 1 {
-                self.masks.set_row_padding([y, z], v);
-                for x in [0, LEN_U32 - 1] {
-                    let i = [x, y, z].i_3d();
-                    self.voxels[i] = v;
-                }
-            }
+    /// Chunk of `voxels` with its masks built from them
+    pub fn from_voxels(voxels: Voxels) -> Self {
+        Self {
+            masks: Masks::from_voxels(&voxels),
+            voxels,
+            ..default()
         }
     }
 
-    pub fn raycast(&self, ray: Ray3d, max: f32) -> [Option<UVec3>; 2] {
-        let origin = ray.origin.to_vec3a();
-        let dir = ray.direction.to_vec3a();
-
-        let mut pos = origin.floor().as_ivec3();
-        let step = dir.signum().as_ivec3();
+    pub fn set(&mut self, p: impl Index3d, v: Option<Voxel>) {
+        self.voxels.set(p.i_3d::<S>(), v);
 
-        let t_delta = dir.recip().abs();
-        let mut t_max = (pos.as_vec3a() + step.max(IVec3::ZERO).as_vec3a() - origin) / dir;
+        self.masks.set(p, v);
 
-        let mut last = None;
-        let mut distance;
+        if let Some(temperature) = &mut self.temperature
+            && let Some(t) = v.and_then(temperature::initial)
+        {
+            temperature.set(p, t);
+        }
 
-        loop {
-            let in_unpad_bounds =
-                pos.cmpge(IVec3::ONE).all() && pos.cmplt(IVec3::splat(LEN as i32 - 1)).all();
-            if in_unpad_bounds {
-                let pos = pos.as_uvec3();
+        self.invalidate_light(p);
+        self.unsaved = true;
+    }
 
-                if self.masks.is_some(pos) {
-                    return [last, Some(pos)];
-                }
+    /// Copy of everything meshing reads, for meshing off the main thread
+    pub fn snapshot(&self) -> BoxChunk<S> {
+        let mut snapshot = BoxChunk::default();
+        snapshot.voxels = self.voxels.clone();
+        snapshot.masks = self.masks.clone();
+        snapshot.flow = self.flow.clone();
+        snapshot.light = self.light.as_ref().map(|light| Box::new(light.snapshot()));
+        snapshot
+    }
 
-                last = Some(pos);
+    /// Copies the voxels and light of `neighbour`'s border into the padding holding the same
+    /// voxels, `offset` being where it is relative to this chunk. Returns the padding voxels that
+    /// changed, the padding isn't saved so the chunk stays as it was
+    pub fn copy_padding(&mut self, neighbour: &Self, offset: IVec3) -> Vec<usize> {
+        let mut changed = Vec::new();
+        for (padding, border) in padding_pairs::<S>(offset) {
+            let v = neighbour.voxels[border];
+            let mut differs = self.voxels[padding] != v;
+            if differs {
+                self.voxels.set(padding, v);
+                self.masks.set(padding, v);
             }
 
-            if t_max.x < t_max.y && t_max.x < t_max.z {
-                pos.x += step.x;
-                distance = t_max.x;
-                t_max.x = distance + t_delta.x;
-            } else if t_max.y < t_max.z {
-                pos.y += step.y;
-                distance = t_max.y;
-                t_max.y = distance + t_delta.y;
-            } else {
-                pos.z += step.z;
-                distance = t_max.z;
-                t_max.z = distance + t_delta.z;
+            if let (Some(light), Some(neighbour)) = (&mut self.light, &neighbour.light) {
+                let packed = neighbour.packed(border);
+                if light.packed(padding) != packed {
+                    light.set_padding(padding, packed);
+                    differs = true;
+                }
             }
 
-            if distance > max {
-                return [last, None];
+            if differs {
+                changed.push(padding);
             }
         }
+        changed
+    }
+}
+
+#[derive(Component, Deref, DerefMut)]
+pub struct BoxChunk<S: ChunkSize = DefaultSize>(Box<Chunk<S>>);
+
+impl<S: ChunkSize> Default for BoxChunk<S> {
+    fn default() -> Self {
+        Self(default())
     }
 }
 
-#[derive(Component, Deref, DerefMut, Default)]
-pub struct BoxChunk(Box<Chunk>);
+impl<S: ChunkSize> From<Chunk<S>> for BoxChunk<S> {
+    fn from(chunk: Chunk<S>) -> Self {
+        Self(Box::new(chunk))
+    }
+}
@@ -0,0 +1,266 @@
+use super::storage::Dense;
+
+/// Widest index, enough for 65536 different values in one chunk
+pub const MAX_BITS: u32 = 16;
+
+/// Bits needed to index `n` entries, a power of two and at least 1
+#[inline]
+fn bits_for(n: usize) -> u32 {
+    (usize::BITS - (n.max(2) - 1).leading_zeros()).next_power_of_two()
+}
+
+/// log2 of the indices that fit in a word. Widths are powers of two so indices fill words
+/// exactly and never straddle two
+#[inline]
+fn per_word_log2(bits: u32) -> u32 {
+    u64::BITS.trailing_zeros() - bits.trailing_zeros()
+}
+
+#[inline]
+fn words_for(bits: u32, len: usize) -> usize {
+    len.div_ceil(1 << per_word_log2(bits))
+}
+
+/// Word of index `i` and where in it the index starts
+#[inline]
+fn locate(bits: u32, i: usize) -> (usize, u32) {
+    let log2 = per_word_log2(bits);
+    (i >> log2, (i & ((1 << log2) - 1)) as u32 * bits)
+}
+
+#[inline]
+fn get(words: &[u64], bits: u32, i: usize) -> usize {
+    let (word, shift) = locate(bits, i);
+    ((words[word] >> shift) & ((1 << bits) - 1)) as usize
+}
+
+#[inline]
+fn put(words: &mut [u64], bits: u32, i: usize, entry: usize) {
+    let (word, shift) = locate(bits, i);
+    let word = &mut words[word];
+    *word = (*word & !(((1 << bits) - 1) << shift)) | ((entry as u64) << shift);
+}
+
+/// Values stored as indices into a local palette of the values in use, packed [`Palette::bits`]
+/// to a word. Widens as values are added and narrows once enough are gone, always to 1, 2, 4, 8
+/// or 16 bits.
+///
+/// The palette and the packed words are all that's needed to rebuild it, so that's what gets
+/// written out
+#[derive(Clone)]
+pub struct Palette<T> {
+    entries: Vec<T>,
+    /// Values pointing at each entry, entries at 0 are free to be reused
+    counts: Vec<u32>,
+    /// Entries above 0
+    live: usize,
+    bits: u32,
+    words: Box<[u64]>,
+    len: usize,
+}
+
+impl<T: Copy + PartialEq> Palette<T> {
+    /// Palette of `len` values packed `bits` to a word as written out, `None` if they don't fit
+    /// together
+    pub fn from_parts(entries: Vec<T>, bits: u32, words: Box<[u64]>, len: usize) -> Option<Self> {
+        let fits = bits.is_power_of_two()
+            && bits <= MAX_BITS
+            && !entries.is_empty()
+            && entries.len() <= 1 << bits
+            && words.len() == words_for(bits, len);
+        if !fits {
+            return None;
+        }
+
+        let mut counts = vec![0; entries.len()];
+        for i in 0..len {
+            *counts.get_mut(get(&words, bits, i))? += 1;
+        }
+
+        Some(Self {
+            live: counts.iter().filter(|&&count| count > 0).count(),
+            entries,
+            counts,
+            bits,
+            words,
+            len,
+        })
+    }
+
+    /// Values indexed by the packed words, some may be free
+    #[inline]
+    pub fn entries(&self) -> &[T] {
+        &self.entries
+    }
+
+    #[inline]
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    #[inline]
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Entry of `v`, taking a free one or adding one if there's none yet
+    fn entry(&mut self, v: T) -> usize {
+        if let Some(e) = self.entries.iter().position(|&entry| entry == v) {
+            return e;
+        }
+
+        let e = match self.counts.iter().position(|&count| count == 0) {
+            Some(free) => {
+                self.entries[free] = v;
+                free
+            }
+            None => {
+                self.entries.push(v);
+                self.counts.push(0);
+                self.entries.len() - 1
+            }
+        };
+
+        if e >> self.bits != 0 {
+            self.repack(self.bits * 2, |e| e);
+        }
+        e
+    }
+
+    /// Packs the indices again at `bits` each, mapping every entry through `remap`
+    fn repack(&mut self, bits: u32, remap: impl Fn(usize) -> usize) {
+        debug_assert!(
+            bits <= MAX_BITS,
+            "palette of {} entries",
+            self.entries.len()
+        );
+
+        let mut words = vec![0; words_for(bits, self.len)].into_boxed_slice();
+        for i in 0..self.len {
+            put(&mut words, bits, i, remap(get(&self.words, self.bits, i)));
+        }
+        self.bits = bits;
+        self.words = words;
+    }
+
+    /// Drops free entries and narrows the indices to what's left
+    fn compact(&mut self) {
+        let mut remap = vec![0; self.entries.len()];
+        let mut entries = Vec::with_capacity(self.live);
+        let mut counts = Vec::with_capacity(self.live);
+
+        for (e, (&entry, &count)) in self.entries.iter().zip(&self.counts).enumerate() {
+            if count > 0 {
+                remap[e] = entries.len();
+                entries.push(entry);
+                counts.push(count);
+            }
+        }
+
+        self.repack(bits_for(entries.len()), |e| remap[e]);
+        self.entries = entries;
+        self.counts = counts;
+    }
+}
+
+impl<T: Copy + PartialEq> Dense<T> for Palette<T> {
+    fn filled(value: T, len: usize) -> Self {
+        let bits = 1;
+        Self {
+            entries: vec![value],
+            counts: vec![len as u32],
+            live: 1,
+            bits,
+            words: vec![0; words_for(bits, len)].into_boxed_slice(),
+            len,
+        }
+    }
+
+    fn from_values(values: Vec<T>) -> Self {
+        let len = values.len();
+        let mut palette = Self::filled(values[0], len);
+        for (i, v) in values.into_iter().enumerate() {
+            palette.set(i, v);
+        }
+        palette
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> &T {
+        &self.entries[get(&self.words, self.bits, i)]
+    }
+
+    fn set(&mut self, i: usize, v: T) {
+        let old = get(&self.words, self.bits, i);
+        if self.entries[old] == v {
+            return;
+        }
+
+        let new = self.entry(v);
+        put(&mut self.words, self.bits, i, new);
+
+        self.counts[new] += 1;
+        if self.counts[new] == 1 {
+            self.live += 1;
+        }
+
+        self.counts[old] -= 1;
+        if self.counts[old] == 0 {
+            self.live -= 1;
+            // a width of slack so edits around a boundary don't repack every time
+            if bits_for(self.live) * 2 < self.bits {
+                self.compact();
+            }
+        }
+    }
+
+    #[inline]
+    fn swap(&mut self, a: usize, b: usize) {
+        let entry_a = get(&self.words, self.bits, a);
+        let entry_b = get(&self.words, self.bits, b);
+        put(&mut self.words, self.bits, a, entry_b);
+        put(&mut self.words, self.bits, b, entry_a);
+    }
+
+    #[inline]
+    fn uniform(&self) -> Option<T> {
+        (self.live == 1).then(|| {
+            let e = self.counts.iter().position(|&count| count > 0).unwrap();
+            self.entries[e]
+        })
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a,
+    {
+        (0..self.len).map(|i| self.get(i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths_stay_powers_of_two() {
+        let mut palette = Palette::filled(0, 4096);
+        for i in 0..300 {
+            palette.set(i, i);
+            assert!(palette.bits().is_power_of_two(), "{} bits", palette.bits());
+        }
+        assert_eq!(palette.bits(), 16);
+        assert!((0..300).all(|i| *palette.get(i) == i));
+
+        for i in 2..300 {
+            palette.set(i, 0);
+        }
+        assert_eq!(palette.bits(), 1);
+        assert!((0..4096).all(|i| *palette.get(i) == (i == 1) as usize));
+    }
+}
@@ -0,0 +1,109 @@
+use bevy::math::bounding::Aabb3d;
+use bevy::prelude::*;
+
+use super::map::Chunks;
+
+impl Chunks<'_, '_> {
+    /// Calls `f` with every loaded voxel overlapping `aabb` and the volume of that overlap
+    fn for_each_overlapping(&self, aabb: Aabb3d, mut f: impl FnMut(IVec3, f32)) {
+        let min = aabb.min.floor().as_ivec3();
+        let max = aabb.max.ceil().as_ivec3();
+
+        for z in min.z..max.z {
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    let p = ivec3(x, y, z);
+
+                    let lo = aabb.min.max(p.as_vec3a());
+                    let hi = aabb.max.min((p + IVec3::ONE).as_vec3a());
+                    let overlap = (hi - lo).max(Vec3A::ZERO);
+
+                    f(p, overlap.x * overlap.y * overlap.z);
+                }
+            }
+        }
+    }
+
+    /// Whether world voxel `p` is loaded and liquid
+    #[inline]
+    pub fn is_liquid(&self, p: IVec3) -> bool {
+        self.get(p)
+            .is_some_and(|(chunk, local)| chunk.masks.is_liquid(local))
+    }
+
+    /// Whether world voxel `p` is loaded and neither empty nor liquid
+    #[inline]
+    pub fn is_solid(&self, p: IVec3) -> bool {
+        self.get(p)
+            .is_some_and(|(chunk, local)| chunk.masks.is_solid(local))
+    }
+
+    /// Whether the voxel containing `pos` is liquid, unloaded isn't
+    pub fn is_liquid_at(&self, pos: Vec3) -> bool {
+        self.is_liquid(pos.floor().as_ivec3())
+    }
+
+    /// Volume of `aabb` that's inside liquid voxels
+    pub fn liquid_volume(&self, aabb: Aabb3d) -> f32 {
+        let mut volume = 0.;
+        self.for_each_overlapping(aabb, |p, overlap| {
+            if self.is_liquid(p) {
+                volume += overlap;
+            }
+        });
+        volume
+    }
+
+    /// Whether `aabb` overlaps any non liquid voxel. Touching doesn't count
+    pub fn intersects_solid(&self, aabb: Aabb3d) -> bool {
+        let mut intersects = false;
+        self.for_each_overlapping(aabb, |p, overlap| {
+            intersects |= overlap > 0. && self.is_solid(p);
+        });
+        intersects
+    }
+
+    /// First voxel hit within `max` of the ray's origin and the one before it, unloaded voxels
+    /// are let through
+    pub fn raycast(&self, ray: Ray3d, max: f32) -> [Option<IVec3>; 2] {
+        let origin = ray.origin.to_vec3a();
+        let dir = ray.direction.to_vec3a();
+
+        let mut pos = origin.floor().as_ivec3();
+        let step = dir.signum().as_ivec3();
+
+        let t_delta = dir.recip().abs();
+        let mut t_max = (pos.as_vec3a() + step.max(IVec3::ZERO).as_vec3a() - origin) / dir;
+
+        let mut last = None;
+        let mut distance;
+
+        loop {
+            if let Some((chunk, local)) = self.get(pos) {
+                if chunk.masks.is_some(local) {
+                    return [last, Some(pos)];
+                }
+
+                last = Some(pos);
+            }
+
+            if t_max.x < t_max.y && t_max.x < t_max.z {
+                pos.x += step.x;
+                distance = t_max.x;
+                t_max.x = distance + t_delta.x;
+            } else if t_max.y < t_max.z {
+                pos.y += step.y;
+                distance = t_max.y;
+                t_max.y = distance + t_delta.y;
+            } else {
+                pos.z += step.z;
+                distance = t_max.z;
+                t_max.z = distance + t_delta.z;
+            }
+
+            if distance > max {
+                return [last, None];
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::io::{self, Read, Write};
+
+use super::palette::Palette;
+use super::storage::{Dense, Storage};
+use super::{Chunk, ChunkSize, Voxel};
+
+const MAGIC: [u8; 4] = *b"VWCH";
+const VERSION: u8 = 1;
+
+const UNIFORM: u8 = 0;
+const PALETTE: u8 = 1;
+
+#[inline]
+fn voxel_to_byte(v: Option<Voxel>) -> u8 {
+    match v {
+        None => 0,
+        Some(Voxel::Liquid) => 1,
+        Some(Voxel::Solid) => 2,
+        Some(Voxel::Ice) => 3,
+        Some(Voxel::Lava) => 4,
+    }
+}
+
+#[inline]
+fn byte_to_voxel(byte: u8) -> io::Result<Option<Voxel>> {
+    Ok(match byte {
+        0 => None,
+        1 => Some(Voxel::Liquid),
+        2 => Some(Voxel::Solid),
+        3 => Some(Voxel::Ice),
+        4 => Some(Voxel::Lava),
+        _ => return Err(invalid("unknown voxel")),
+    })
+}
+
+#[inline]
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_bytes<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl<S: ChunkSize> Chunk<S> {
+    /// Writes the voxels out, the one value if they're uniform and the palette and its packed
+    /// words otherwise. Everything else is rebuilt from them on [`Chunk::read`]
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&MAGIC)?;
+        w.write_all(&[VERSION])?;
+        w.write_all(&(S::VOL as u32).to_le_bytes())?;
+
+        match &self.voxels {
+            Storage::Uniform { value, .. } => w.write_all(&[UNIFORM, voxel_to_byte(*value)]),
+            Storage::Dense(palette) => {
+                w.write_all(&[PALETTE, palette.bits() as u8])?;
+
+                let entries = palette.entries();
+                w.write_all(&(entries.len() as u16).to_le_bytes())?;
+                for &entry in entries {
+                    w.write_all(&[voxel_to_byte(entry)])?;
+                }
+
+                let words = palette.words();
+                w.write_all(&(words.len() as u32).to_le_bytes())?;
+                for word in words {
+                    w.write_all(&word.to_le_bytes())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Chunk written by [`Chunk::write`], without light or temperature
+    pub fn read(r: &mut impl Read) -> io::Result<Self> {
+        if read_bytes(r)? != MAGIC {
+            return Err(invalid("not a chunk"));
+        }
+        if read_bytes::<1>(r)? != [VERSION] {
+            return Err(invalid("unsupported chunk version"));
+        }
+        if u32::from_le_bytes(read_bytes(r)?) as usize != S::VOL {
+            return Err(invalid("chunk of another size"));
+        }
+
+        let voxels = match read_bytes(r)? {
+            [UNIFORM] => Storage::uniform(byte_to_voxel(read_bytes::<1>(r)?[0])?, S::VOL),
+            [PALETTE] => {
+                let [bits] = read_bytes(r)?;
+
+                let count = u16::from_le_bytes(read_bytes(r)?) as usize;
+                let entries = (0..count)
+                    .map(|_| byte_to_voxel(read_bytes::<1>(r)?[0]))
+                    .collect::<io::Result<Vec<_>>>()?;
+
+                let count = u32::from_le_bytes(read_bytes(r)?) as usize;
+                let words = (0..count)
+                    .map(|_| read_bytes(r).map(u64::from_le_bytes))
+                    .collect::<io::Result<Box<[_]>>>()?;
+
+                let palette = Palette::from_parts(entries, bits as u32, words, S::VOL)
+                    .ok_or_else(|| invalid("corrupt palette"))?;
+                match palette.uniform() {
+                    Some(value) => Storage::uniform(value, S::VOL),
+                    None => Storage::Dense(palette),
+                }
+            }
+            _ => return Err(invalid("unknown voxel storage")),
+        };
+
+        Ok(Self::from_voxels(voxels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Mask;
+    use crate::chunk::size::Size32;
+
+    fn round_trip(chunk: &Chunk<Size32>) -> Chunk<Size32> {
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        Chunk::read(&mut bytes.as_slice()).unwrap()
+    }
+
+    fn assert_same_masks(a: &Mask<Size32>, b: &Mask<Size32>) {
+        assert!(a.iter().eq(b.iter()));
+    }
+
+    fn assert_same(a: &Chunk<Size32>, b: &Chunk<Size32>) {
+        assert!(a.voxels.iter().eq(b.voxels.iter()));
+        let (a, b) = (&a.masks, &b.masks);
+        assert_same_masks(&a.transparent_mask, &b.transparent_mask);
+        let (a, b) = (&a.dblt_masks.front, &b.dblt_masks.front);
+        assert_same_masks(&a.some_mask, &b.some_mask);
+        assert_same_masks(&a.liquid_mask, &b.liquid_mask);
+    }
+
+    #[test]
+    fn uniform_round_trip() {
+        let chunk = Chunk::<Size32>::from_fn(|_| Some(Voxel::Solid));
+        let read = round_trip(&chunk);
+        assert!(matches!(
+            read.voxels,
+            Storage::Uniform {
+                value: Some(Voxel::Solid),
+                ..
+            }
+        ));
+        assert_same(&chunk, &read);
+    }
+
+    #[test]
+    fn palette_round_trip() {
+        let chunk = Chunk::<Size32>::from_fn(|[x, y, z]| match (x + y + z) % 4 {
+            0 => None,
+            1 => Some(Voxel::Liquid),
+            2 => Some(Voxel::Solid),
+            _ => Some(Voxel::Lava),
+        });
+        let read = round_trip(&chunk);
+        assert!(matches!(read.voxels, Storage::Dense(_)));
+        assert_same(&chunk, &read);
+    }
+
+    #[test]
+    fn corrupt_palette_is_invalid() {
+        let chunk = Chunk::<Size32>::from_fn(|[x, _, _]| (x < 8).then_some(Voxel::Solid));
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+
+        // magic, version and volume, then the storage tag and the width
+        assert_eq!(bytes[9], PALETTE);
+        bytes[10] = 3;
+        let e = Chunk::<Size32>::read(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,123 @@
+use bit_iter::BitIter;
+use std::fmt::Debug;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, Not, Shl, Shr, Sub};
+
+/// Bits of one row of voxels along X, bit `x` for voxel `x`
+pub trait Row:
+    Copy
+    + Eq
+    + Debug
+    + Send
+    + Sync
+    + 'static
+    + Not<Output = Self>
+    + BitAnd<Output = Self>
+    + BitAndAssign
+    + BitOr<Output = Self>
+    + BitOrAssign
+    + BitXor<Output = Self>
+    + Shl<u32, Output = Self>
+    + Shr<u32, Output = Self>
+    + Sub<Output = Self>
+{
+    const ZERO: Self;
+    const ONE: Self;
+    /// First and last voxel of the row
+    const PAD: Self;
+
+    /// Whether bit `x` is set
+    #[inline]
+    fn bit(self, x: u32) -> bool {
+        (self >> x) & Self::ONE != Self::ZERO
+    }
+
+    fn trailing_zeros(self) -> u32;
+
+    fn wrapping_shl(self, rhs: u32) -> Self;
+
+    fn wrapping_shr(self, rhs: u32) -> Self;
+
+    /// Set bits, lowest first
+    fn ones(self) -> impl Iterator<Item = usize>;
+
+    /// Low bits of `bits`, rows kept as `u64` whatever the chunk size are narrowed with this
+    fn truncate(bits: u64) -> Self;
+
+    fn widen(self) -> u64;
+}
+
+macro_rules! impl_row {
+    ($($t:ty),*) => {$(
+        impl Row for $t {
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+            const PAD: Self = (1 << (<$t>::BITS - 1)) | 1;
+
+            #[inline]
+            fn trailing_zeros(self) -> u32 {
+                <$t>::trailing_zeros(self)
+            }
+
+            #[inline]
+            fn wrapping_shl(self, rhs: u32) -> Self {
+                <$t>::wrapping_shl(self, rhs)
+            }
+
+            #[inline]
+            fn wrapping_shr(self, rhs: u32) -> Self {
+                <$t>::wrapping_shr(self, rhs)
+            }
+
+            #[inline]
+            fn ones(self) -> impl Iterator<Item = usize> {
+                BitIter::from(self)
+            }
+
+            #[inline]
+            fn truncate(bits: u64) -> Self {
+                bits as $t
+            }
+
+            #[inline]
+            fn widen(self) -> u64 {
+                self as u64
+            }
+        }
+    )*};
+}
+
+impl_row!(u32, u64);
+
+/// Dimensions of a chunk, padding included. Chunks are cubes of [`ChunkSize::LEN`] voxels a side
+/// with a bit of [`ChunkSize::Row`] per voxel of a row
+pub trait ChunkSize: Copy + Default + Debug + Send + Sync + 'static {
+    const BITS: u32;
+
+    const LEN: usize = 1 << Self::BITS;
+    const LEN_U32: u32 = Self::LEN as u32;
+    const AREA: usize = Self::LEN * Self::LEN;
+    const VOL: usize = Self::AREA * Self::LEN;
+
+    type Row: Row;
+}
+
+/// 32³ voxels in rows of `u32`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Size32;
+
+impl ChunkSize for Size32 {
+    const BITS: u32 = 5;
+    type Row = u32;
+}
+
+/// 64³ voxels in rows of `u64`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Size64;
+
+impl ChunkSize for Size64 {
+    const BITS: u32 = 6;
+    type Row = u64;
+}
+
+const _: () = assert!(Size32::LEN == u32::BITS as usize);
+const _: () = assert!(Size64::LEN == u64::BITS as usize);
@@ -0,0 +1,172 @@
+use std::iter::repeat_n;
+use std::ops::{Index, IndexMut};
+
+/// How a [`Storage`] keeps its values once they differ
+pub trait Dense<T>: Clone {
+    /// `len` copies of `value`
+    fn filled(value: T, len: usize) -> Self;
+
+    fn from_values(values: Vec<T>) -> Self;
+
+    fn len(&self) -> usize;
+
+    fn get(&self, i: usize) -> &T;
+
+    fn set(&mut self, i: usize, v: T);
+
+    fn swap(&mut self, a: usize, b: usize);
+
+    /// The value shared by all if they're known to be the same again, checked after every write
+    #[inline]
+    fn uniform(&self) -> Option<T> {
+        None
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a;
+}
+
+impl<T: Copy> Dense<T> for Box<[T]> {
+    #[inline]
+    fn filled(value: T, len: usize) -> Self {
+        vec![value; len].into_boxed_slice()
+    }
+
+    #[inline]
+    fn from_values(values: Vec<T>) -> Self {
+        values.into_boxed_slice()
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> &T {
+        &self[i]
+    }
+
+    #[inline]
+    fn set(&mut self, i: usize, v: T) {
+        self[i] = v;
+    }
+
+    #[inline]
+    fn swap(&mut self, a: usize, b: usize) {
+        <[T]>::swap(self, a, b);
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a,
+    {
+        <[T]>::iter(self)
+    }
+}
+
+/// `len` values that share one until a write makes them differ
+#[derive(Clone)]
+pub enum Storage<T, D = Box<[T]>> {
+    /// Every value is `value`
+    Uniform {
+        value: T,
+        len: usize,
+    },
+    Dense(D),
+}
+
+impl<T: Copy + PartialEq, D: Dense<T>> Storage<T, D> {
+    #[inline]
+    pub fn uniform(value: T, len: usize) -> Self {
+        Self::Uniform { value, len }
+    }
+
+    /// `f` of every index, uniform if that's the same for all
+    pub fn from_fn(len: usize, f: impl FnMut(usize) -> T) -> Self {
+        let values: Vec<T> = (0..len).map(f).collect();
+        match values.first() {
+            Some(&first) if values.iter().all(|&v| v == first) => Self::uniform(first, len),
+            _ => Self::Dense(D::from_values(values)),
+        }
+    }
+
+    /// Every value as its own, copying the shared one out first if uniform
+    pub fn make_dense(&mut self) -> &mut D {
+        if let Self::Uniform { value, len } = *self {
+            *self = Self::Dense(D::filled(value, len));
+        }
+        match self {
+            Self::Dense(values) => values,
+            Self::Uniform { .. } => unreachable!(),
+        }
+    }
+
+    /// Sets `i` to `v`, going dense only if that makes it differ from the rest and back to
+    /// uniform if the dense storage notices they're all the same again
+    #[inline]
+    pub fn set(&mut self, i: usize, v: T) {
+        match self {
+            Self::Uniform { value, len } => {
+                debug_assert!(i < *len, "index {i} out of {len}");
+                if *value != v {
+                    self.make_dense().set(i, v);
+                }
+            }
+            Self::Dense(values) => {
+                values.set(i, v);
+                if let Some(value) = values.uniform() {
+                    *self = Self::uniform(value, values.len());
+                }
+            }
+        }
+    }
+
+    /// Sets `i` to `f` of its value, see [`Storage::set`]
+    #[inline]
+    pub fn update(&mut self, i: usize, f: impl FnOnce(T) -> T) {
+        self.set(i, f(self[i]));
+    }
+
+    #[inline]
+    pub fn swap(&mut self, a: usize, b: usize) {
+        if let Self::Dense(values) = self {
+            values.swap(a, b);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let (uniform, dense) = match self {
+            Self::Uniform { value, len } => (Some(repeat_n(value, *len)), None),
+            Self::Dense(values) => (None, Some(values.iter())),
+        };
+        uniform
+            .into_iter()
+            .flatten()
+            .chain(dense.into_iter().flatten())
+    }
+}
+
+impl<T: Copy + PartialEq, D: Dense<T>> Index<usize> for Storage<T, D> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, i: usize) -> &T {
+        match self {
+            Self::Uniform { value, len } => {
+                debug_assert!(i < *len, "index {i} out of {len}");
+                value
+            }
+            Self::Dense(values) => values.get(i),
+        }
+    }
+}
+
+/// Goes dense, use [`Storage::set`] or [`Storage::update`] where the value may not change
+impl<T: Copy + PartialEq> IndexMut<usize> for Storage<T> {
+    #[inline]
+    fn index_mut(&mut self, i: usize) -> &mut T {
+        &mut self.make_dense()[i]
+    }
+}
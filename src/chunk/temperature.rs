@@ -0,0 +1,251 @@
+use bevy::prelude::*;
+use std::marker::PhantomData;
+
+use super::double_buffered::DoubleBuffered;
+use super::index::*;
+use super::{Chunk, ChunkSize, DefaultSize, Row, Voxel, Voxels};
+
+pub const AMBIENT: f32 = 20.0;
+
+/// Liquid below this freezes
+pub const FREEZE: f32 = -1.0;
+/// Ice above this melts
+pub const MELT: f32 = 1.0;
+/// Lava below this cools into stone
+pub const SOLIDIFY: f32 = 700.0;
+
+const ICE: f32 = -10.0;
+const LAVA: f32 = 1200.0;
+
+/// Chunks without temperature get it once a neighbour's border is further than this from ambient
+pub const NOTICEABLE: f32 = 1.0;
+
+/// Fraction of the distance to ambient that air covers every tick
+const AIR_COOLING: f32 = 0.05;
+
+/// Temperature a freshly placed voxel starts at, `None` keeps whatever was there
+#[inline]
+pub fn initial(v: Voxel) -> Option<f32> {
+    match v {
+        Voxel::Ice => Some(ICE),
+        Voxel::Lava => Some(LAVA),
+        _ => None,
+    }
+}
+
+/// Fraction of the distance to the neighbour mean covered every tick
+#[inline]
+fn conductivity(v: Option<Voxel>) -> f32 {
+    match v {
+        None => 0.1,
+        Some(Voxel::Liquid | Voxel::Ice) => 0.2,
+        Some(Voxel::Solid) => 0.1,
+        Some(Voxel::Lava) => 0.02,
+    }
+}
+
+pub struct Temperature<S: ChunkSize = DefaultSize> {
+    pub ambient: f32,
+    values: DoubleBuffered<Box<[f32]>>,
+    /// Voxels that froze, melted or solidified since last drained, with what they were before
+    pub phase_changes: Vec<(usize, Voxel)>,
+    _size: PhantomData<S>,
+}
+
+impl<S: ChunkSize> Temperature<S> {
+    pub fn new(ambient: f32) -> Self {
+        let values = || vec![ambient; S::VOL].into_boxed_slice();
+
+        Self {
+            ambient,
+            values: DoubleBuffered {
+                front: values(),
+                back: values(),
+            },
+            phase_changes: Vec::new(),
+            _size: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, p: impl Index3d) -> f32 {
+        self.values.front[p.i_3d::<S>()]
+    }
+
+    /// Temperature of `p` before the last [`Chunk::temperature_tick`] diffused heat
+    #[inline]
+    pub fn before_diffusion(&self, p: impl Index3d) -> f32 {
+        self.values.back[p.i_3d::<S>()]
+    }
+
+    #[inline]
+    pub fn set(&mut self, p: impl Index3d, t: f32) {
+        self.values.front[p.i_3d::<S>()] = t;
+    }
+
+    #[inline]
+    pub fn swap(&mut self, a: impl Index3d, b: impl Index3d) {
+        self.values.front.swap(a.i_3d::<S>(), b.i_3d::<S>());
+    }
+
+    /// Temperatures of the unpadded voxels on the side facing `normal`, in
+    /// [`side_indices`] order
+    pub fn border(&self, normal: IVec3) -> impl Iterator<Item = f32> {
+        side_indices::<S>(normal, false).map(|i| self.values.front[i])
+    }
+
+    /// Sets the padding on the side facing `normal` to the neighbour's [`Temperature::border`]
+    /// facing back, which heat diffuses against
+    pub fn set_padding(&mut self, normal: IVec3, border: impl IntoIterator<Item = f32>) {
+        for (i, t) in side_indices::<S>(normal, true).zip(border) {
+            // both buffers since diffusing swaps them without writing the padding
+            self.values.front[i] = t;
+            self.values.back[i] = t;
+        }
+    }
+
+    /// Padding is never written here, it holds the neighbours' border or ambient without one
+    fn diffuse(&mut self, voxels: &Voxels) {
+        let DoubleBuffered { front, back } = &mut self.values;
+
+        let [stride_x, stride_y, stride_z] = [
+            Shape3d::<S>::STRIDE_X,
+            Shape3d::<S>::STRIDE_Y,
+            Shape3d::<S>::STRIDE_Z,
+        ];
+
+        for z in 1..S::LEN_U32 - 1 {
+            for y in 1..S::LEN_U32 - 1 {
+                for x in 1..S::LEN_U32 - 1 {
+                    let i = [x, y, z].i_3d::<S>();
+
+                    let t = front[i];
+                    let mean = (front[i + stride_x]
+                        + front[i - stride_x]
+                        + front[i + stride_y]
+                        + front[i - stride_y]
+                        + front[i + stride_z]
+                        + front[i - stride_z])
+                        / 6.;
+
+                    let v = voxels[i];
+                    let mut next = t + conductivity(v) * (mean - t);
+                    if v.is_none() {
+                        next += AIR_COOLING * (self.ambient - next);
+                    }
+
+                    back[i] = next;
+                }
+            }
+        }
+
+        self.values.swap();
+    }
+}
+
+impl<S: ChunkSize> Chunk<S> {
+    pub fn enable_temperature(&mut self, ambient: f32) {
+        let mut temperature = Temperature::new(ambient);
+        for (i, v) in self.voxels.iter().enumerate() {
+            if let Some(t) = v.and_then(initial) {
+                temperature.set(i, t);
+            }
+        }
+        self.temperature = Some(Box::new(temperature));
+    }
+
+    /// Diffuses heat then flips voxels that crossed a phase threshold.
+    ///
+    /// Writes go to both mask buffers so call this outside of [`Chunk::liquid_tick`]
+    pub fn temperature_tick(&mut self) {
+        let Some(temperature) = &mut self.temperature else {
+            return;
+        };
+
+        temperature.diffuse(&self.voxels);
+
+        let some_mask = self.masks.some_mask();
+
+        for z in 1..S::LEN_U32 - 1 {
+            for y in 1..S::LEN_U32 - 1 {
+                let i_2d = [y, z].i_2d::<S>();
+
+                for x in (some_mask[i_2d] & !S::Row::PAD).ones() {
+                    let i_3d = (x, i_2d).i_3d::<S>();
+                    let t = temperature.values.front[i_3d];
+
+                    let Some(prev) = self.voxels[i_3d] else {
+                        continue;
+                    };
+
+                    let next = match prev {
+                        Voxel::Liquid if t < FREEZE => Voxel::Ice,
+                        Voxel::Ice if t > MELT => Voxel::Liquid,
+                        Voxel::Lava if t < SOLIDIFY => Voxel::Solid,
+                        _ => continue,
+                    };
+
+                    self.voxels.set(i_3d, Some(next));
+                    temperature.phase_changes.push((i_3d, prev));
+                }
+            }
+        }
+
+        for &(i_3d, _) in &temperature.phase_changes {
+            self.masks.set(i_3d, self.voxels[i_3d]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Mask;
+    use crate::chunk::size::Size32;
+
+    /// Sets the temperature of `p` to `t`, ticks once and returns what changed phase
+    fn tick_at(chunk: &mut Chunk<Size32>, p: [u32; 3], t: f32) -> Vec<(usize, Voxel)> {
+        chunk.temperature.as_mut().unwrap().set(p, t);
+        chunk.temperature_tick();
+        std::mem::take(&mut chunk.temperature.as_mut().unwrap().phase_changes)
+    }
+
+    /// Asserts `p` holds `v` in the voxels and every mask, both buffers of the tick masks included
+    fn assert_voxel(chunk: &Chunk<Size32>, p: [u32; 3], v: Voxel) {
+        assert_eq!(chunk.voxels[p.i_3d::<Size32>()], Some(v));
+
+        let (x, i_2d) = p.x_and_i_2d::<Size32>();
+        let bit = |mask: &Mask<Size32>| mask[i_2d].bit(x);
+        for masks in [&chunk.masks.dblt_masks.front, &chunk.masks.dblt_masks.back] {
+            assert!(bit(&masks.some_mask), "{v:?} some");
+            assert_eq!(bit(&masks.liquid_mask), v.is_liquid(), "{v:?} liquid");
+        }
+        assert_eq!(
+            bit(&chunk.masks.transparent_mask),
+            v.is_transparent(),
+            "{v:?} transparent"
+        );
+    }
+
+    #[test]
+    fn freezes_melts_and_solidifies() {
+        let p = [10, 10, 10];
+        let i = p.i_3d::<Size32>();
+        let mut chunk = Chunk::<Size32>::default();
+        chunk.set(p, Some(Voxel::Liquid));
+        chunk.enable_temperature(AMBIENT);
+
+        assert_eq!(tick_at(&mut chunk, p, -20.), [(i, Voxel::Liquid)]);
+        assert_voxel(&chunk, p, Voxel::Ice);
+
+        assert_eq!(tick_at(&mut chunk, p, 30.), [(i, Voxel::Ice)]);
+        assert_voxel(&chunk, p, Voxel::Liquid);
+
+        chunk.set(p, Some(Voxel::Lava));
+        assert_eq!(tick_at(&mut chunk, p, 100.), [(i, Voxel::Lava)]);
+        assert_voxel(&chunk, p, Voxel::Solid);
+
+        // nothing left to change
+        assert!(tick_at(&mut chunk, p, 100.).is_empty());
+    }
+}
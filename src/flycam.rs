@@ -5,6 +5,8 @@ use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
 use bevy::window::{CursorGrabMode, CursorOptions, PrimaryWindow};
 
+use crate::physics::controller::Walking;
+
 pub mod prelude {
     pub use crate::*;
 }
@@ -95,7 +97,7 @@ fn player_move(
     cursor_options: Query<&CursorOptions, With<PrimaryWindow>>,
     settings: Res<MovementSettings>,
     key_bindings: Res<KeyBindings>,
-    mut query: Query<(&FlyCam, &mut Transform)>, //    mut query: Query<&mut Transform, With<FlyCam>>,
+    mut query: Query<(&FlyCam, &mut Transform), Without<Walking>>, //    mut query: Query<&mut Transform, With<FlyCam>>,
 ) {
     if let Ok(cursor_options) = cursor_options.single() {
         for (_camera, mut transform) in query.iter_mut() {
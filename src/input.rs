@@ -2,57 +2,86 @@ use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
 use bevy::prelude::*;
 use std::time::Duration;
 
+use crate::chunk::map::{ChunkMap, Chunks};
 use crate::chunk::{BoxChunk, Voxel};
 use crate::flycam::FlyCam;
 use crate::render::ChunkMeshChanges;
+use crate::render::water::SmoothWater;
+use crate::simulation::{Rewind, SimulationMode, SimulationSettings, set_voxel};
 
 const MIN_TIMESTEP: Duration = Duration::from_nanos(500_000);
 const MAX_TIMESTEP: Duration = Duration::from_secs(2);
 
+const MAX_SUBSTEPS: u32 = 64;
+
 pub struct GameInputPlugin;
 
 impl Plugin for GameInputPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (chunk_input, time_input));
+        app.add_systems(
+            Update,
+            (
+                chunk_input,
+                time_input,
+                substep_input,
+                simulation_input,
+                water_input,
+            ),
+        );
     }
 }
 
 #[derive(Component)]
 pub struct SelectedMarker;
 
+#[allow(clippy::too_many_arguments)]
 fn chunk_input(
     mut transforms: Query<&mut Transform>,
-    chunk: Single<(&mut BoxChunk, &mut ChunkMeshChanges)>,
+    mut chunks: ParamSet<(Chunks, Query<(&mut BoxChunk, &mut ChunkMeshChanges)>)>,
+    map: Res<ChunkMap>,
     selected: Single<(Entity, &mut Visibility), With<SelectedMarker>>,
     player: Single<Entity, With<FlyCam>>,
     input: Res<ButtonInput<MouseButton>>,
-    mut anchor: Local<UVec3>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut anchor: Local<IVec3>,
+    mut brush: Local<Option<Voxel>>,
 ) {
-    let (mut chunk, mut changes) = chunk.into_inner();
-
     let ray = {
         let transform = transforms.get(*player).unwrap();
         let origin = transform.translation;
         let direction = transform.forward();
         Ray3d::new(origin, direction)
     };
-    let [prev, dst] = chunk.raycast(ray, 20.);
+    let [prev, dst] = chunks.p0().raycast(ray, 20.);
 
     let (entity, mut visibility) = selected.into_inner();
     let mut transform = transforms.get_mut(entity).unwrap();
 
+    for (key, voxel) in [
+        (KeyCode::Digit1, Voxel::Liquid),
+        (KeyCode::Digit2, Voxel::Lava),
+        (KeyCode::Digit3, Voxel::Ice),
+    ] {
+        if keys.just_pressed(key) {
+            *brush = Some(voxel);
+        }
+    }
+
     if input.pressed(MouseButton::Middle)
         && let Some(p) = prev
     {
-        chunk.set(p, Some(Voxel::Liquid));
-        changes.push(p);
+        set_voxel(
+            &mut chunks.p1(),
+            &map,
+            p,
+            Some(brush.unwrap_or(Voxel::Liquid)),
+        );
     }
 
     if input.just_pressed(MouseButton::Left)
         && let Some(p) = dst
     {
-        chunk.set(p, None);
-        changes.push(p);
+        set_voxel(&mut chunks.p1(), &map, p, None);
     }
 
     if let Some(p) = prev.or(dst) {
@@ -63,22 +92,24 @@ fn chunk_input(
             for z in [min.z, max.z] {
                 for y in min.y..=max.y {
                     for x in min.x..=max.x {
-                        chunk.set([x, y, z], Some(Voxel::Solid));
-                        changes.push([x, y, z]);
+                        set_voxel(&mut chunks.p1(), &map, ivec3(x, y, z), Some(Voxel::Solid));
                     }
                 }
             }
             for z in min.z + 1..=max.z - 1 {
                 for x in min.x..=max.x {
-                    chunk.set([x, min.y, z], Some(Voxel::Solid));
-                    changes.push([x, min.y, z]);
+                    set_voxel(
+                        &mut chunks.p1(),
+                        &map,
+                        ivec3(x, min.y, z),
+                        Some(Voxel::Solid),
+                    );
                 }
             }
             for z in min.z + 1..=max.z - 1 {
                 for y in min.y + 1..=max.y {
                     for x in [min.x, max.x] {
-                        chunk.set([x, y, z], Some(Voxel::Solid));
-                        changes.push([x, y, z]);
+                        set_voxel(&mut chunks.p1(), &map, ivec3(x, y, z), Some(Voxel::Solid));
                     }
                 }
             }
@@ -91,7 +122,7 @@ fn chunk_input(
         let min = p.min(*anchor);
         let max = p.max(*anchor);
 
-        let scale = (max + UVec3::ONE).as_vec3() - min.as_vec3();
+        let scale = (max + IVec3::ONE).as_vec3() - min.as_vec3();
         let translation = min.as_vec3() + scale / 2.;
 
         transform.scale = scale;
@@ -124,3 +155,47 @@ fn time_input(mut time_step: ResMut<Time<Fixed>>, mut scroll: MessageReader<Mous
         time_step.set_timestep(new);
     }
 }
+
+fn substep_input(mut settings: ResMut<SimulationSettings>, keys: Res<ButtonInput<KeyCode>>) {
+    if keys.just_pressed(KeyCode::BracketRight) {
+        settings.substeps = (settings.substeps * 2).min(MAX_SUBSTEPS);
+    }
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        settings.substeps = (settings.substeps / 2).max(1);
+    }
+}
+
+fn water_input(mut smooth_water: ResMut<SmoothWater>, keys: Res<ButtonInput<KeyCode>>) {
+    if keys.just_pressed(KeyCode::KeyV) {
+        **smooth_water = !**smooth_water;
+    }
+}
+
+fn simulation_input(
+    mut mode: ResMut<SimulationMode>,
+    mut rewind: MessageWriter<Rewind>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    if keys.just_pressed(KeyCode::KeyP) {
+        *mode = match *mode {
+            SimulationMode::Running => SimulationMode::Paused,
+            _ => SimulationMode::Running,
+        };
+    }
+
+    if keys.just_pressed(KeyCode::Period) {
+        *mode = match *mode {
+            SimulationMode::Stepping(n) => SimulationMode::Stepping(n + 1),
+            _ => SimulationMode::Stepping(1),
+        };
+    }
+
+    if keys.just_pressed(KeyCode::Comma) {
+        let n = if keys.pressed(KeyCode::ShiftLeft) {
+            10
+        } else {
+            1
+        };
+        rewind.write(Rewind(n));
+    }
+}
@@ -3,24 +3,39 @@ mod chunk;
 mod flycam;
 mod input;
 mod jumpscare;
+mod physics;
 mod render;
+mod simulation;
+mod streaming;
+mod underwater;
+mod worldgen;
 
 use std::f32::consts::PI;
 
 use bevy::asset::{embedded_asset, load_embedded_asset};
-use bevy::camera::visibility::NoFrustumCulling;
 use bevy::core_pipeline::Skybox;
-use bevy::mesh::{Indices, PrimitiveTopology};
+use bevy::light::NotShadowCaster;
 use bevy::prelude::*;
 use bevy::render::view::NoIndirectDrawing;
 
-use crate::chunk::{BoxChunk, Voxel};
+use crate::chunk::BoxChunk;
+use crate::chunk::map::{ChunkMap, ChunkPos};
 use crate::flycam::{FlyCam, NoCameraPlayerPlugin};
 use crate::input::{GameInputPlugin, SelectedMarker};
 use crate::jumpscare::JumpscarePlugin;
-use crate::render::mesher::MESHER;
+use crate::physics::currents::Currents;
+use crate::physics::{BuoyancyPlugin, CurrentsPlugin, PlayerControllerPlugin};
+use crate::render::lod::{ChunkLod, CollapsedChunk, update_chunk_lods, update_collapsed_chunks};
+use crate::render::meshing::{MeshTasks, apply_mesh_tasks, clear_dirty_slices, spawn_mesh_tasks};
 use crate::render::pipeline::QuadInstancingPlugin;
-use crate::render::{ChunkMesh, ChunkMeshChanges};
+use crate::render::water::{
+    ChunkWater, LiquidMaterial, LiquidSettings, WaterPlugin, ripple_normal_map,
+};
+use crate::render::{ChunkMesh, ChunkMeshChanges, chunk_aabb};
+use crate::simulation::{SimulationPlugin, relight};
+use crate::streaming::StreamingPlugin;
+use crate::underwater::{Underwater, UnderwaterPlugin};
+use crate::worldgen::WorldGenPlugin;
 
 fn main() {
     App::new().add_plugins(Game).run();
@@ -35,16 +50,35 @@ impl Plugin for Game {
             NoCameraPlayerPlugin,
             GameInputPlugin,
             QuadInstancingPlugin,
+            WaterPlugin,
             JumpscarePlugin,
+            SimulationPlugin,
+            BuoyancyPlugin,
+            CurrentsPlugin,
+            PlayerControllerPlugin,
+            UnderwaterPlugin,
+            WorldGenPlugin,
+            StreamingPlugin,
         ));
 
         embedded_asset!(app, "skybox.ktx2");
 
-        app.insert_resource(Time::<Fixed>::from_hz(10.0));
+        app.insert_resource(Time::<Fixed>::from_hz(10.0))
+            .init_resource::<ChunkMap>();
 
         app.add_systems(Startup, setup)
-            .add_systems(FixedUpdate, liquid_tick)
-            .add_systems(Update, remesh_chunk);
+            .add_systems(First, clear_dirty_slices)
+            .add_systems(
+                Update,
+                (
+                    update_chunk_lods,
+                    update_collapsed_chunks,
+                    spawn_mesh_tasks,
+                    apply_mesh_tasks,
+                )
+                    .chain()
+                    .after(relight),
+            );
     }
 }
 
@@ -52,11 +86,18 @@ fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut liquid_materials: ResMut<Assets<LiquidMaterial>>,
+    mut images: ResMut<Assets<Image>>,
     asset_server: Res<AssetServer>,
 ) {
+    let skybox = load_embedded_asset!(&*asset_server, "skybox.ktx2");
+
     // light
     commands.spawn((
-        DirectionalLight::default(),
+        DirectionalLight {
+            shadows_enabled: true,
+            ..default()
+        },
         Transform::default().looking_at(
             Vec3::NEG_Y
                 .rotate_towards(Vec3::Z, PI / 5.)
@@ -73,105 +114,64 @@ fn setup(
             ..default()
         },
         Skybox {
-            image: load_embedded_asset!(&*asset_server, "skybox.ktx2"),
+            image: skybox.clone(),
             brightness: 1000.0,
             ..default()
         },
         Camera3d::default(),
         FlyCam,
+        Currents { strength: 10. },
+        Underwater::default(),
         NoIndirectDrawing, // TODO: what does this do?
     ));
 
-    // chunk aabb
-    commands.spawn((
-        Mesh3d(meshes.add(cube_wireframe_mesh(62.))),
-        MeshMaterial3d(materials.add(Color::WHITE)),
-        Transform::from_xyz(32.0, 32.0, 32.0),
-    ));
-
     // selected aabb
     commands.spawn((
         Mesh3d(meshes.add(Cuboid::from_length(1.))),
         MeshMaterial3d(materials.add(Color::srgba(0.5, 0., 0., 0.25))),
         Visibility::Hidden,
+        NotShadowCaster,
         SelectedMarker,
     ));
 
-    // chunk
-    let mut chunk = BoxChunk::default();
-    chunk.fill_padding(Some(Voxel::Solid));
-    let mesh = MESHER.with_borrow_mut(|mesher| mesher.mesh(&chunk, IVec3::ZERO));
-    commands.spawn((
-        chunk,
-        mesh,
-        ChunkMeshChanges::default(),
-        Mesh3d(meshes.add(Rectangle::from_length(1.))),
-        NoFrustumCulling,
-    ));
-}
-
-fn liquid_tick(chunk: Single<(&mut BoxChunk, &mut ChunkMeshChanges)>, mut tick: Local<u64>) {
-    let (mut chunk, mut changes) = chunk.into_inner();
-
-    chunk.liquid_tick(*tick);
-    chunk.masks.dblt_masks.copy_back_to_front();
-
-    for (dst, src) in chunk.dst_to_src.drain() {
-        changes.push(dst);
-        changes.push(src);
-    }
-
-    *tick += 1;
-}
-
-fn remesh_chunk(chunk: Single<(&BoxChunk, &mut ChunkMesh, &mut ChunkMeshChanges)>) {
-    let (chunk, mut mesh, mut changes) = chunk.into_inner();
-
-    if changes.is_empty() {
-        return;
-    }
-
-    MESHER.with_borrow_mut(|mesher| {
-        mesher.remesh(chunk, IVec3::ZERO, &mut mesh, *changes);
+    commands.insert_resource(ChunkAssets {
+        water_material: liquid_materials.add(LiquidMaterial {
+            settings: LiquidSettings::default(),
+            skybox,
+            normal_map: images.add(ripple_normal_map()),
+        }),
+        placeholder: meshes.add(Rectangle::from_length(1.)),
     });
+}
 
-    changes.clear();
+/// Handles every chunk shares
+#[derive(Resource)]
+pub struct ChunkAssets {
+    water_material: Handle<LiquidMaterial>,
+    /// Stands in for the chunk's mesh so it's drawn at all, the quads are what's rendered
+    placeholder: Handle<Mesh>,
 }
 
-// AI
-pub fn cube_wireframe_mesh(size: f32) -> Mesh {
-    let h = size / 2.;
-
-    let corners = [
-        vec3(-h, -h, -h),
-        vec3(h, -h, -h),
-        vec3(h, h, -h),
-        vec3(-h, h, -h),
-        vec3(-h, -h, h),
-        vec3(h, -h, h),
-        vec3(h, h, h),
-        vec3(-h, h, h),
-    ];
-    let edges = [
-        [0, 1],
-        [1, 2],
-        [2, 3],
-        [3, 0],
-        [4, 5],
-        [5, 6],
-        [6, 7],
-        [7, 4],
-        [0, 4],
-        [1, 5],
-        [2, 6],
-        [3, 7],
-    ];
-
-    let positions = corners.iter().map(|c| c.to_array()).collect::<Vec<_>>();
-    let indices = edges.into_iter().flatten().collect::<Vec<_>>();
-
-    let mut mesh = Mesh::new(PrimitiveTopology::LineList, default());
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-    mesh.insert_indices(Indices::U32(indices));
-    mesh
+/// Spawns `chunk` at `pos` with everything it needs to be meshed and rendered
+pub fn spawn_chunk(commands: &mut Commands, assets: &ChunkAssets, pos: ChunkPos, chunk: BoxChunk) {
+    // both meshed by the chunk's first meshing job
+    let water = commands
+        .spawn((
+            MeshMaterial3d(assets.water_material.clone()),
+            Transform::default(),
+            chunk_aabb(pos),
+        ))
+        .id();
+    commands.spawn((
+        chunk,
+        pos,
+        ChunkMesh::new(default()),
+        ChunkMeshChanges::default(),
+        MeshTasks::default(),
+        ChunkLod::default(),
+        CollapsedChunk::default(),
+        ChunkWater(water),
+        Mesh3d(assets.placeholder.clone()),
+        chunk_aabb(pos),
+    ));
 }
@@ -0,0 +1,138 @@
+use bevy::math::bounding::Aabb3d;
+use bevy::prelude::*;
+
+use super::currents::Currents;
+use super::{GRAVITY, MAX_DT, Velocity, move_and_collide};
+use crate::chunk::map::Chunks;
+use crate::flycam::FlyCam;
+
+const CRATE_HALF_SIZE: Vec3 = Vec3::splat(0.75);
+
+pub struct BuoyancyPlugin;
+
+impl Plugin for BuoyancyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, init_assets)
+            .add_systems(Update, (spawn_crate, buoyancy));
+    }
+}
+
+/// An aabb that sinks or floats depending on how much of it is inside liquid voxels
+#[derive(Component)]
+#[require(Transform, Velocity)]
+pub struct Buoyant {
+    pub half_size: Vec3,
+    /// Relative to liquid, below `1.0` floats
+    pub density: f32,
+    /// Fraction of velocity lost per second when fully submerged
+    pub drag: f32,
+}
+
+#[derive(Resource)]
+struct CrateAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn init_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(CrateAssets {
+        mesh: meshes.add(Cuboid::from_size(CRATE_HALF_SIZE * 2.)),
+        material: materials.add(Color::srgb(0.6, 0.4, 0.2)),
+    });
+}
+
+fn spawn_crate(
+    mut commands: Commands,
+    assets: Res<CrateAssets>,
+    player: Single<&Transform, With<FlyCam>>,
+    input: Res<ButtonInput<KeyCode>>,
+) {
+    if input.just_pressed(KeyCode::KeyC) {
+        commands.spawn((
+            Mesh3d(assets.mesh.clone()),
+            MeshMaterial3d(assets.material.clone()),
+            Transform::from_translation(player.translation + player.forward() * 4.),
+            Buoyant {
+                half_size: CRATE_HALF_SIZE,
+                density: 0.5,
+                drag: 0.9,
+            },
+            Currents { strength: 40. },
+        ));
+    }
+}
+
+fn buoyancy(
+    chunks: Chunks,
+    bodies: Query<(&Buoyant, &mut Transform, &mut Velocity)>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs().min(MAX_DT);
+
+    for (body, mut transform, mut velocity) in bodies {
+        let aabb = Aabb3d::new(transform.translation, body.half_size);
+
+        let volume = body.half_size.element_product() * 8.;
+        let submerged = chunks.liquid_volume(aabb) / volume;
+
+        // per unit mass so gravity and buoyancy cancel out at `submerged == density`
+        velocity.y += GRAVITY * (submerged / body.density - 1.) * dt;
+        **velocity *= (1. - body.drag * submerged).powf(dt);
+
+        move_and_collide(
+            &chunks,
+            &mut transform.translation,
+            &mut velocity,
+            body.half_size,
+            dt,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::chunk::map::{ChunkMap, ChunkPos};
+    use crate::chunk::{BoxChunk, Chunk, DefaultSize, Voxel};
+
+    #[test]
+    fn crate_floats_as_deep_as_its_density() {
+        let mut app = App::new();
+        app.init_resource::<ChunkMap>()
+            .init_resource::<Time>()
+            .add_systems(Update, buoyancy);
+
+        // surface at y 16
+        let chunk = Chunk::<DefaultSize>::from_fn(|[_, y, _]| (y < 16).then_some(Voxel::Liquid));
+        app.world_mut()
+            .spawn((BoxChunk::from(chunk), ChunkPos(IVec3::ZERO)));
+        let body = app
+            .world_mut()
+            .spawn((
+                Transform::from_xyz(8., 20., 8.),
+                Buoyant {
+                    half_size: CRATE_HALF_SIZE,
+                    density: 0.5,
+                    drag: 0.9,
+                },
+            ))
+            .id();
+
+        for _ in 0..600 {
+            app.world_mut()
+                .resource_mut::<Time>()
+                .advance_by(Duration::from_secs_f32(MAX_DT));
+            app.update();
+        }
+
+        // half of it below the surface
+        let y = app.world().get::<Transform>(body).unwrap().translation.y;
+        assert!((y - 16.).abs() < 0.05, "settled at {y}");
+    }
+}
@@ -0,0 +1,174 @@
+use bevy::math::bounding::Aabb3d;
+use bevy::prelude::*;
+use bevy::window::{CursorGrabMode, CursorOptions, PrimaryWindow};
+
+use super::{GRAVITY, MAX_DT, Velocity, move_and_collide};
+use crate::chunk::map::Chunks;
+use crate::flycam::{FlyCam, KeyBindings};
+
+const HALF_SIZE: Vec3 = vec3(0.3, 0.9, 0.3);
+/// From the center of the body up to the camera
+const EYE_OFFSET: Vec3 = vec3(0.0, 0.7, 0.0);
+
+const WALK_SPEED: f32 = 6.0;
+const JUMP_SPEED: f32 = 8.0;
+/// How quickly horizontal velocity reaches the input velocity on ground
+const WALK_ACCEL: f32 = 12.0;
+
+const SWIM_SPEED: f32 = 4.0;
+const SWIM_ACCEL: f32 = 3.0;
+const SWIM_GRAVITY: f32 = GRAVITY * 0.2;
+/// Fraction of velocity lost per second while swimming
+const SWIM_DRAG: f32 = 0.8;
+/// Fraction of the body that has to be in liquid to swim, the head in liquid always swims
+const SWIM_SUBMERGED: f32 = 0.3;
+
+pub struct PlayerControllerPlugin;
+
+impl Plugin for PlayerControllerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (toggle_walking, walk).chain());
+    }
+}
+
+/// Replaces [`FlyCam`] movement with gravity and collision against solid voxels
+#[derive(Component, Default)]
+#[require(Velocity)]
+pub struct Walking {
+    pub grounded: bool,
+    pub swimming: bool,
+}
+
+fn toggle_walking(
+    mut commands: Commands,
+    player: Single<(Entity, Has<Walking>), With<FlyCam>>,
+    input: Res<ButtonInput<KeyCode>>,
+) {
+    if input.just_pressed(KeyCode::KeyF) {
+        let (entity, walking) = *player;
+        if walking {
+            commands.entity(entity).remove::<(Walking, Velocity)>();
+        } else {
+            commands.entity(entity).insert(Walking::default());
+        }
+    }
+}
+
+fn walk(
+    chunks: Chunks,
+    player: Single<(&mut Walking, &mut Transform, &mut Velocity)>,
+    cursor_options: Single<&CursorOptions, With<PrimaryWindow>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    time: Res<Time>,
+) {
+    let (mut walking, mut transform, mut velocity) = player.into_inner();
+    let dt = time.delta_secs().min(MAX_DT);
+
+    let mut input = Vec3::ZERO;
+    if cursor_options.grab_mode != CursorGrabMode::None {
+        let local_z = transform.local_z();
+        let forward = -vec3(local_z.x, 0., local_z.z).normalize_or_zero();
+        let right = vec3(local_z.z, 0., -local_z.x).normalize_or_zero();
+
+        for (key, dir) in [
+            (key_bindings.move_forward, forward),
+            (key_bindings.move_backward, -forward),
+            (key_bindings.move_left, -right),
+            (key_bindings.move_right, right),
+            (key_bindings.move_ascend, Vec3::Y),
+            (key_bindings.move_descend, Vec3::NEG_Y),
+        ] {
+            if keys.pressed(key) {
+                input += dir;
+            }
+        }
+    }
+
+    let center = transform.translation - EYE_OFFSET;
+    let body = Aabb3d::new(center, HALF_SIZE);
+
+    let submerged = chunks.liquid_volume(body) / (HALF_SIZE.element_product() * 8.);
+    walking.swimming = chunks.is_liquid_at(transform.translation) || submerged > SWIM_SUBMERGED;
+    walking.grounded = chunks.intersects_solid(Aabb3d::new(center - Vec3::Y * 0.05, HALF_SIZE));
+
+    if walking.swimming {
+        let target = input.normalize_or_zero() * SWIM_SPEED;
+        let accel = (target - **velocity) * (1. - (-SWIM_ACCEL * dt).exp());
+        **velocity += accel;
+        **velocity *= (1. - SWIM_DRAG).powf(dt);
+        velocity.y -= SWIM_GRAVITY * dt;
+    } else {
+        let target = vec3(input.x, 0., input.z).normalize_or_zero() * WALK_SPEED;
+        let t = 1. - (-WALK_ACCEL * dt).exp();
+        velocity.x += (target.x - velocity.x) * t;
+        velocity.z += (target.z - velocity.z) * t;
+        velocity.y -= GRAVITY * dt;
+
+        if walking.grounded && keys.just_pressed(key_bindings.move_ascend) {
+            velocity.y = JUMP_SPEED;
+        }
+    }
+
+    let mut center = center;
+    move_and_collide(&chunks, &mut center, &mut velocity, HALF_SIZE, dt);
+    transform.translation = center + EYE_OFFSET;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::chunk::map::{ChunkMap, ChunkPos};
+    use crate::chunk::{BoxChunk, Chunk, DefaultSize, Voxel};
+
+    /// Drops the player from `y` into a chunk of `voxel` below y 4, returning it after `steps`
+    fn drop_into(voxel: Voxel, y: f32, steps: u32) -> (Walking, Vec3) {
+        let mut app = App::new();
+        app.init_resource::<ChunkMap>()
+            .init_resource::<Time>()
+            .init_resource::<ButtonInput<KeyCode>>()
+            .init_resource::<KeyBindings>()
+            .add_systems(Update, walk);
+
+        let chunk = Chunk::<DefaultSize>::from_fn(|[_, y, _]| (y < 4).then_some(voxel));
+        app.world_mut()
+            .spawn((BoxChunk::from(chunk), ChunkPos(IVec3::ZERO)));
+        app.world_mut()
+            .spawn((PrimaryWindow, CursorOptions::default()));
+        let player = app
+            .world_mut()
+            .spawn((Transform::from_xyz(8., y, 8.), Walking::default()))
+            .id();
+
+        for _ in 0..steps {
+            app.world_mut()
+                .resource_mut::<Time>()
+                .advance_by(Duration::from_secs_f32(MAX_DT));
+            app.update();
+        }
+
+        let mut player = app.world_mut().entity_mut(player);
+        let translation = player.get::<Transform>().unwrap().translation;
+        (player.take::<Walking>().unwrap(), translation)
+    }
+
+    #[test]
+    fn lands_on_solid_voxels() {
+        let (walking, eye) = drop_into(Voxel::Solid, 10., 60);
+        let feet = eye.y - EYE_OFFSET.y - HALF_SIZE.y;
+
+        assert!((4.0..4.05).contains(&feet), "feet at {feet}");
+        assert!(walking.grounded);
+        assert!(!walking.swimming);
+    }
+
+    #[test]
+    fn swims_in_liquid() {
+        let (walking, _) = drop_into(Voxel::Liquid, 3., 1);
+
+        assert!(walking.swimming);
+        assert!(!walking.grounded);
+    }
+}
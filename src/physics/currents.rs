@@ -0,0 +1,38 @@
+use bevy::prelude::*;
+
+use super::{MAX_DT, Velocity};
+use crate::chunk::map::Chunks;
+
+pub struct CurrentsPlugin;
+
+impl Plugin for CurrentsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, currents);
+    }
+}
+
+/// Gets carried along by flowing liquid. Pushes [`Velocity`] if there is one, otherwise moves
+/// the [`Transform`] directly
+#[derive(Component)]
+pub struct Currents {
+    /// Units per second (squared with [`Velocity`]) per voxel per tick of flow
+    pub strength: f32,
+}
+
+fn currents(
+    chunks: Chunks,
+    query: Query<(&Currents, &mut Transform, Option<&mut Velocity>)>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs().min(MAX_DT);
+
+    for (currents, mut transform, velocity) in query {
+        let push = chunks.flow_at(transform.translation) * currents.strength * dt;
+
+        if let Some(mut velocity) = velocity {
+            **velocity += push;
+        } else {
+            transform.translation += push;
+        }
+    }
+}
@@ -0,0 +1,41 @@
+pub mod buoyancy;
+pub mod controller;
+pub mod currents;
+
+use bevy::math::bounding::Aabb3d;
+use bevy::prelude::*;
+
+use crate::chunk::map::Chunks;
+
+pub use buoyancy::BuoyancyPlugin;
+pub use controller::PlayerControllerPlugin;
+pub use currents::CurrentsPlugin;
+
+pub const GRAVITY: f32 = 20.0;
+
+/// Frames longer than this are simulated as if they were this long
+pub const MAX_DT: f32 = 1. / 20.;
+
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct Velocity(pub Vec3);
+
+/// Moves `translation` by `velocity * dt` one axis at a time, stopping (and zeroing velocity on)
+/// any axis that would push an aabb of `half_size` into a solid voxel
+pub fn move_and_collide(
+    chunks: &Chunks,
+    translation: &mut Vec3,
+    velocity: &mut Vec3,
+    half_size: Vec3,
+    dt: f32,
+) {
+    for axis in 0..3 {
+        let mut next = *translation;
+        next[axis] += velocity[axis] * dt;
+
+        if chunks.intersects_solid(Aabb3d::new(next, half_size)) {
+            velocity[axis] = 0.;
+        } else {
+            *translation = next;
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use bevy::math::U64Vec3;
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use enum_map::EnumMap;
+use std::ops::Range;
+
+use super::ChunkMeshChanges;
+use super::Face::{self, *};
+use crate::chunk::index::*;
+use crate::chunk::map::{ChunkMap, ChunkPos, INNER};
+use crate::chunk::{BoxChunk, Chunk, ChunkSize, DefaultSize, Voxel};
+use crate::flycam::FlyCam;
+
+/// Distance in voxels from the camera to a chunk's center past which it drops to the next level
+const LOD_DISTANCES: [f32; 2] = [2.5 * INNER as f32, 5. * INNER as f32];
+
+/// How many voxels along each axis collapse into one when meshing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lod {
+    #[default]
+    Full,
+    Half,
+    Quarter,
+}
+
+impl Lod {
+    #[inline]
+    pub fn size(self) -> u32 {
+        match self {
+            Lod::Full => 1,
+            Lod::Half => 2,
+            Lod::Quarter => 4,
+        }
+    }
+
+    #[inline]
+    fn for_distance(distance: f32) -> Self {
+        if distance < LOD_DISTANCES[0] {
+            Lod::Full
+        } else if distance < LOD_DISTANCES[1] {
+            Lod::Half
+        } else {
+            Lod::Quarter
+        }
+    }
+}
+
+/// Level of detail a chunk is meshed at and the sides it leaves open to its neighbours
+#[derive(Component, Clone, Copy, PartialEq, Default)]
+pub struct ChunkLod {
+    pub lod: Lod,
+    /// Sides where the neighbour is at another level, so its surface may not line up with ours
+    /// and would leave cracks. Their padding counts as empty so the chunk walls itself off,
+    /// except in front of liquid which is never walled
+    pub seams: EnumMap<Face, bool>,
+}
+
+/// The chunk as it's meshed when below full detail or with seams, `None` otherwise. Kept between
+/// remeshes so only blocks with changes collapse again
+#[derive(Component, Default)]
+pub struct CollapsedChunk(pub Option<BoxChunk>);
+
+/// Order ties between equally common voxels are broken in, walls win so they don't open up
+const PRIORITY: [Voxel; 4] = [Voxel::Solid, Voxel::Ice, Voxel::Lava, Voxel::Liquid];
+
+/// What a block collapses into: empty unless at least half of it is filled, otherwise its most
+/// common voxel
+fn collapse_block<S: ChunkSize>(chunk: &Chunk<S>, block: [Range<u32>; 3]) -> Option<Voxel> {
+    let mut counts = [0; PRIORITY.len()];
+    let mut len = 0;
+    for i in block_indices::<S>(block) {
+        len += 1;
+        if let Some(v) = chunk.voxels[i] {
+            counts[PRIORITY.iter().position(|&p| p == v).unwrap()] += 1;
+        }
+    }
+
+    if counts.iter().sum::<u32>() * 2 < len {
+        return None;
+    }
+    // max_by_key keeps the last maximum, so go from lowest to highest priority
+    (0..PRIORITY.len())
+        .rev()
+        .max_by_key(|&k| counts[k])
+        .map(|k| PRIORITY[k])
+}
+
+/// Copy of `chunk` with every block of [`Lod::size`]³ voxels set to what it collapses into. The
+/// padding is copied as is, see [`update_collapsed_chunks`] for what it becomes
+pub fn collapse<S: ChunkSize>(chunk: &Chunk<S>, lod: Lod) -> BoxChunk<S> {
+    let size = lod.size();
+    let per_axis = (S::LEN_U32 - 2).div_ceil(size);
+    let collapsed: Vec<_> = blocks::<S>(size)
+        .map(|block| collapse_block(chunk, block))
+        .collect();
+
+    let last = S::LEN_U32 - 1;
+    let mut collapsed = BoxChunk::from(Chunk::from_fn(|p| {
+        if p.contains(&0) || p.contains(&last) {
+            chunk.voxels[p.i_3d::<S>()]
+        } else {
+            let [x, y, z] = p.map(|c| (c - 1) / size);
+            collapsed[((z * per_axis + y) * per_axis + x) as usize]
+        }
+    }));
+
+    collapsed.light = chunk
+        .light
+        .as_ref()
+        .map(|light| Box::new(light.collapsed(size)));
+    collapsed
+}
+
+/// Collapses again the blocks of `collapsed` that hold any of `changes`
+fn recollapse<S: ChunkSize>(
+    chunk: &Chunk<S>,
+    collapsed: &mut Chunk<S>,
+    lod: Lod,
+    changes: ChunkMeshChanges,
+) {
+    let bits = |r: &Range<u32>| ((1u64 << r.len()) - 1) << r.start;
+    let U64Vec3 { x, y, z } = changes.rows;
+
+    for block in blocks::<S>(lod.size()) {
+        let [xs, ys, zs] = &block;
+        if bits(xs) & x == 0 || bits(ys) & y == 0 || bits(zs) & z == 0 {
+            continue;
+        }
+
+        if changes.voxels {
+            let v = collapse_block(chunk, block.clone());
+            for i in block_indices::<S>(block.clone()) {
+                collapsed.voxels.set(i, v);
+                collapsed.masks.set(i, v);
+            }
+        }
+
+        if let (Some(light), Some(collapsed)) = (&chunk.light, &mut collapsed.light) {
+            light.collapse_block(collapsed, block);
+        }
+    }
+
+    let padding = 1 | 1 << (S::LEN_U32 - 1);
+    if (x | y | z) & padding != 0
+        && let (Some(light), Some(collapsed)) = (&chunk.light, &mut collapsed.light)
+    {
+        light.copy_padding(collapsed);
+    }
+}
+
+/// Grows every changed row of a chunk of size `S` to the whole blocks it's part of
+pub fn expand<S: ChunkSize>(changes: ChunkMeshChanges, lod: Lod) -> ChunkMeshChanges {
+    let size = lod.size();
+    let expand = |bits: u64| {
+        let mut expanded = 0;
+        for start in (1..S::LEN_U32 - 1).step_by(size as usize) {
+            let block = ((1u64 << size) - 1) << start;
+            if bits & block != 0 {
+                expanded |= block;
+            }
+        }
+        expanded
+    };
+
+    let U64Vec3 { x, y, z } = changes.rows;
+    ChunkMeshChanges {
+        rows: U64Vec3::new(expand(x), expand(y), expand(z)),
+        ..changes
+    }
+}
+
+/// Picks each chunk's level by its distance to the camera and opens seams towards neighbours at
+/// another level
+pub fn update_chunk_lods(
+    camera: Single<&Transform, With<FlyCam>>,
+    mut chunks: Query<(&ChunkPos, &mut ChunkLod)>,
+) {
+    let eye = camera.translation;
+    let lods: HashMap<IVec3, Lod> = chunks
+        .iter()
+        .map(|(pos, _)| {
+            let center = pos.origin().as_vec3() + DefaultSize::LEN_U32 as f32 / 2.;
+            (**pos, Lod::for_distance(eye.distance(center)))
+        })
+        .collect();
+
+    for (pos, mut chunk_lod) in &mut chunks {
+        let lod = lods[&**pos];
+        let seams = EnumMap::from_fn(|f: Face| {
+            let neighbour = **pos + f.normal();
+            lods.get(&neighbour).is_some_and(|&n| n != lod)
+        });
+
+        chunk_lod.set_if_neq(ChunkLod { lod, seams });
+    }
+}
+
+/// Keeps [`CollapsedChunk`] up to date, then fills the padding of each side from the
+/// neighbour's collapsed border where both are at the same level so their surfaces meet, and
+/// empties it on seams. Changed padding remeshes the border next to it
+#[allow(clippy::type_complexity)]
+pub fn update_collapsed_chunks(
+    map: Res<ChunkMap>,
+    mut chunks: Query<(
+        Entity,
+        &ChunkPos,
+        &BoxChunk,
+        Ref<ChunkLod>,
+        &mut ChunkMeshChanges,
+        &mut CollapsedChunk,
+    )>,
+) {
+    let mut changed = HashSet::<IVec3>::default();
+
+    for (_, pos, chunk, lod, changes, mut collapsed) in &mut chunks {
+        if lod.lod == Lod::Full && !lod.seams.values().any(|&seam| seam) {
+            if collapsed.0.take().is_some() {
+                changed.insert(**pos);
+            }
+            continue;
+        }
+
+        match &mut collapsed.0 {
+            Some(collapsed)
+                if !lod.is_changed() && chunk.light.is_some() == collapsed.light.is_some() =>
+            {
+                if changes.is_empty() {
+                    continue;
+                }
+                recollapse(chunk, collapsed, lod.lod, *changes);
+            }
+            _ => collapsed.0 = Some(collapse(chunk, lod.lod)),
+        }
+        changed.insert(**pos);
+    }
+
+    let last = DefaultSize::LEN_U32 - 1;
+    let mut padding = Vec::new();
+
+    for (entity, pos, chunk, lod, _, collapsed) in &chunks {
+        let Some(collapsed) = &collapsed.0 else {
+            continue;
+        };
+
+        for f in Face::ALL {
+            let neighbour = **pos + f.normal();
+            if !changed.contains(&**pos) && !changed.contains(&neighbour) {
+                continue;
+            }
+            let neighbour = map
+                .get(&neighbour)
+                .and_then(|&n| chunks.get(n).ok())
+                .and_then(|(.., collapsed)| collapsed.0.as_ref());
+
+            // axis across the side, the padding's and the border's coordinate on it and the
+            // neighbour's voxel behind the padding
+            let (axis, side, border, behind) = match f {
+                PosX => (0, last, last - 1, 1),
+                PosY => (1, last, last - 1, 1),
+                PosZ => (2, last, last - 1, 1),
+                NegX => (0, 0, 1, last - 1),
+                NegY => (1, 0, 1, last - 1),
+                NegZ => (2, 0, 1, last - 1),
+            };
+
+            for v in 1..last {
+                for u in 1..last {
+                    let at = |c: u32| {
+                        let mut p = [0; 3];
+                        p[axis] = c;
+                        p[(axis + 1) % 3] = u;
+                        p[(axis + 2) % 3] = v;
+                        p.i_3d::<DefaultSize>()
+                    };
+
+                    let real = chunk.voxels[at(side)];
+                    let voxel = if lod.seams[f] {
+                        collapsed.voxels[at(border)]
+                            .is_some_and(Voxel::is_liquid)
+                            .then_some(real)
+                            .flatten()
+                    } else {
+                        neighbour.map_or(real, |neighbour| neighbour.voxels[at(behind)])
+                    };
+
+                    if collapsed.voxels[at(side)] != voxel {
+                        padding.push((entity, at(side), at(border), voxel));
+                    }
+                }
+            }
+        }
+    }
+
+    for (entity, i, border, voxel) in padding {
+        let (.., mut changes, mut collapsed) = chunks.get_mut(entity).unwrap();
+        let collapsed = collapsed.0.as_mut().unwrap();
+        collapsed.voxels.set(i, voxel);
+        collapsed.masks.set(i, voxel);
+        changes.push::<DefaultSize>(border);
+    }
+}
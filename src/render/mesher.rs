@@ -1,5 +1,3 @@
-// TODO: transparency
-
 use bevy::prelude::*;
 use enum_map::{EnumMap, enum_map};
 use std::cell::RefCell;
@@ -7,11 +5,11 @@ use std::ops::Range;
 
 use super::*;
 
-use crate::chunk::{AREA, Chunk, LEN, LEN_U32, PAD_MASK, Voxel, index::*};
-
-const UPWARD_STRIDE_X: usize = STRIDE_X_3D;
-const FORWARD_STRIDE_X: usize = STRIDE_X_3D;
-const FORWARD_STRIDE_Y: usize = STRIDE_Y_3D;
+use super::lod::{Lod, expand};
+use super::water::water_row;
+use crate::chunk::light::MAX_LIGHT;
+use crate::chunk::masks::Masks;
+use crate::chunk::{Chunk, ChunkSize, DefaultSize, Mask, Row, Voxel, index::*};
 
 thread_local! {
     pub static MESHER: RefCell<Mesher> = default();
@@ -19,164 +17,185 @@ thread_local! {
 
 /// Reusable buffers for meshing
 #[derive(Deref, DerefMut)]
-pub struct Mesher {
-    quads: Vec<Quad>,
+pub struct Mesher<S: ChunkSize = DefaultSize> {
+    quads: EnumMap<Pass, Vec<Quad>>,
     #[deref]
-    inner: InnerMesher,
+    inner: InnerMesher<S>,
 }
 
-impl Default for Mesher {
+impl<S: ChunkSize> Default for Mesher<S> {
     fn default() -> Self {
         Self {
-            quads: Vec::new(),
+            quads: EnumMap::default(),
             inner: InnerMesher {
-                visible_masks: Box::new(enum_map! { _ => [0; AREA] }),
-                upward_merged: Box::new([0; LEN]),
-                forward_merged: Box::new([0; AREA]),
+                visible_masks: enum_map! { _ => vec![S::Row::ZERO; S::AREA].into_boxed_slice() },
+                upward_merged: vec![0; S::LEN].into_boxed_slice(),
+                forward_merged: vec![0; S::AREA].into_boxed_slice(),
+                smooth_water: false,
+                lod: Lod::Full,
             },
         }
     }
 }
 
-pub struct InnerMesher {
-    visible_masks: Box<EnumMap<Face, [u64; AREA]>>,
-    upward_merged: Box<[u8; LEN]>,
-    forward_merged: Box<[u8; AREA]>,
+pub struct InnerMesher<S: ChunkSize = DefaultSize> {
+    visible_masks: EnumMap<Face, Box<[S::Row]>>,
+    upward_merged: Box<[u8]>,
+    forward_merged: Box<[u8]>,
+    /// Leaves water faces to the smooth surface in [`super::water`]
+    pub smooth_water: bool,
+    /// Level of detail, chunks below full detail should come from [`super::lod::collapse`]
+    pub lod: Lod,
 }
 
-impl InnerMesher {
-    fn build_all_visible_masks(&mut self, chunk: &Chunk) {
+impl<S: ChunkSize> InnerMesher<S> {
+    const UPWARD_STRIDE_X: usize = Shape3d::<S>::STRIDE_X;
+    const FORWARD_STRIDE_X: usize = Shape3d::<S>::STRIDE_X;
+    const FORWARD_STRIDE_Y: usize = Shape3d::<S>::STRIDE_Y;
+
+    /// Voxels of a row that aren't greedy meshed
+    #[inline]
+    fn skipped(&self) -> fn(&Chunk<S>, usize) -> S::Row {
+        if self.smooth_water {
+            water_row
+        } else {
+            |_, _| S::Row::ZERO
+        }
+    }
+
+    fn build_all_visible_masks(&mut self, chunk: &Chunk<S>) {
         let some_mask = chunk.masks.some_mask();
+        let skipped = self.skipped();
 
         for f in Face::ALL {
             let visible_mask = &mut self.visible_masks[f];
 
-            for z in 1..LEN_U32 - 1 {
-                for y in 1..LEN_U32 - 1 {
-                    let i_2d = [y, z].i_2d();
+            for z in 1..S::LEN_U32 - 1 {
+                for y in 1..S::LEN_U32 - 1 {
+                    let i_2d = [y, z].i_2d::<S>();
 
-                    let some = some_mask[i_2d];
-                    let unpad_some = some & !PAD_MASK;
+                    let unpad_some = some_mask[i_2d] & !S::Row::PAD & !skipped(chunk, i_2d);
 
-                    if unpad_some == 0 {
-                        visible_mask[i_2d] = 0;
+                    if unpad_some == S::Row::ZERO {
+                        visible_mask[i_2d] = S::Row::ZERO;
                     } else {
-                        let adj_some = match f {
-                            PosX => some >> 1,
-                            NegX => some << 1,
-                            PosY => some_mask[i_2d + STRIDE_Y_2D],
-                            NegY => some_mask[i_2d - STRIDE_Y_2D],
-                            PosZ => some_mask[i_2d + STRIDE_Z_2D],
-                            NegZ => some_mask[i_2d - STRIDE_Z_2D],
-                        };
-
-                        visible_mask[i_2d] = unpad_some & !adj_some;
+                        visible_mask[i_2d] = unpad_some & unculled(&chunk.masks, i_2d, f);
                     }
                 }
             }
         }
     }
 
-    fn build_visible_masks(&mut self, chunk: &Chunk, remesh: U64Vec3) {
+    fn build_visible_masks(&mut self, chunk: &Chunk<S>, remesh: U64Vec3) {
         let some_mask = chunk.masks.some_mask();
+        let skipped = self.skipped();
 
-        let other_z = !remesh.z & !PAD_MASK;
-        let other_y = !remesh.y & !PAD_MASK;
+        let unpadded = (!S::Row::PAD).widen();
+        let other_z = !remesh.z & unpadded;
+        let other_y = !remesh.y & unpadded;
 
         for f in Face::ALL {
             let visible_mask = &mut self.visible_masks[f];
 
-            let mut handler = |y: u32, z: u32, xs: u64| {
-                let i_2d = [y, z].i_2d();
+            let mut handler = |y: u32, z: u32, xs: S::Row| {
+                let i_2d = [y, z].i_2d::<S>();
 
-                let some = some_mask[i_2d];
-                let unpad_some = some & !PAD_MASK & xs;
+                let unpad_some = some_mask[i_2d] & !S::Row::PAD & !skipped(chunk, i_2d) & xs;
 
-                if unpad_some == 0 {
-                    visible_mask[i_2d] = 0;
+                if unpad_some == S::Row::ZERO {
+                    visible_mask[i_2d] = S::Row::ZERO;
                 } else {
-                    let adj_some = match f {
-                        PosX => some >> 1,
-                        NegX => some << 1,
-                        PosY => some_mask[i_2d + STRIDE_Y_2D],
-                        NegY => some_mask[i_2d - STRIDE_Y_2D],
-                        PosZ => some_mask[i_2d + STRIDE_Z_2D],
-                        NegZ => some_mask[i_2d - STRIDE_Z_2D],
-                    };
-
-                    visible_mask[i_2d] = unpad_some & !adj_some;
+                    visible_mask[i_2d] = unpad_some & unculled(&chunk.masks, i_2d, f);
                 }
             };
 
             for z in BitIter::from(remesh.z).map(u32) {
-                for y in 1..LEN_U32 - 1 {
-                    handler(y, z, !0)
+                for y in 1..S::LEN_U32 - 1 {
+                    handler(y, z, !S::Row::ZERO)
                 }
             }
 
             for z in BitIter::from(other_z).map(u32) {
                 for y in BitIter::from(remesh.y).map(u32) {
-                    handler(y, z, !0)
+                    handler(y, z, !S::Row::ZERO)
                 }
             }
 
             for z in BitIter::from(other_z).map(u32) {
                 for y in BitIter::from(other_y).map(u32) {
-                    handler(y, z, remesh.x)
+                    handler(y, z, S::Row::truncate(remesh.x))
                 }
             }
         }
     }
 
-    fn merged_quads(&mut self, chunk: &Chunk, origin: IVec3) -> EnumMap<Face, Vec<Quad>> {
-        let mut map = EnumMap::default();
+    fn merged_quads(
+        &mut self,
+        chunk: &Chunk<S>,
+        origin: IVec3,
+    ) -> EnumMap<Pass, EnumMap<Face, Vec<Quad>>> {
+        let mut map: EnumMap<Pass, EnumMap<Face, Vec<Quad>>> = EnumMap::default();
         for f in Face::ALL {
-            let quads = &mut map[f];
+            let mut quads = EnumMap::default();
             match f {
-                PosX | NegX => self.merge_x(chunk, origin, !0, f, quads),
-                PosY | NegY => self.merge_y(chunk, origin, 1..LEN_U32 - 1, f, quads),
-                PosZ | NegZ => self.merge_z(chunk, origin, 1..LEN_U32 - 1, f, quads),
+                PosX | NegX => self.merge_x(chunk, origin, !S::Row::ZERO, f, &mut quads),
+                PosY | NegY => self.merge_y(chunk, origin, 1..S::LEN_U32 - 1, f, &mut quads),
+                PosZ | NegZ => self.merge_z(chunk, origin, 1..S::LEN_U32 - 1, f, &mut quads),
+            }
+            for (pass, quads) in quads {
+                map[pass][f] = quads;
             }
         }
         map
     }
 
-    fn merge_x(&mut self, chunk: &Chunk, origin: IVec3, xs: u64, f: Face, quads: &mut Vec<Quad>) {
+    fn merge_x(
+        &mut self,
+        chunk: &Chunk<S>,
+        origin: IVec3,
+        xs: S::Row,
+        f: Face,
+        quads: &mut EnumMap<Pass, Vec<Quad>>,
+    ) {
+        let lod = self.lod;
         let visible_mask = &self.visible_masks[f];
 
-        for z in 1..LEN_U32 - 1 {
-            for y in 1..LEN_U32 - 1 {
-                let i_2d = [y, z].i_2d();
+        for z in 1..S::LEN_U32 - 1 {
+            for y in 1..S::LEN_U32 - 1 {
+                let i_2d = [y, z].i_2d::<S>();
                 let mut visible = visible_mask[i_2d] & xs;
 
-                let upward_visible = visible_mask[i_2d + STRIDE_Y_2D] & xs;
-                let forward_visible = visible_mask[i_2d + STRIDE_Z_2D] & xs;
+                let upward_visible = visible_mask[i_2d + Shape2d::<S>::STRIDE_Y] & xs;
+                let forward_visible = visible_mask[i_2d + Shape2d::<S>::STRIDE_Z] & xs;
 
-                while visible != 0 {
+                while visible != S::Row::ZERO {
                     let x = visible.trailing_zeros();
-                    visible &= visible - 1;
+                    visible &= visible - S::Row::ONE;
 
                     let upward_i = x as usize;
-                    let forward_i = [x, y].i_2d();
+                    let forward_i = [x, y].i_2d::<S>();
 
-                    let i_3d = (x, i_2d).i_3d();
+                    let i_3d = (x, i_2d).i_3d::<S>();
                     let voxel_opt = chunk.voxels[i_3d];
                     let voxel = voxel_opt.unwrap();
+                    let shading = face_shading(chunk, i_3d, f, lod);
 
                     // forward merging
                     if self.upward_merged[upward_i] == 0
-                        && (forward_visible >> x) & 1 != 0
-                        && voxel_opt == chunk.voxels[i_3d + STRIDE_Z_3D]
+                        && forward_visible.bit(x)
+                        && voxel_opt == chunk.voxels[i_3d + Shape3d::<S>::STRIDE_Z]
+                        && shading == face_shading(chunk, i_3d + Shape3d::<S>::STRIDE_Z, f, lod)
                     {
                         self.forward_merged[forward_i] += 1;
                         continue;
                     }
 
                     // upward merging
-                    if (upward_visible >> x) & 1 != 0
+                    if upward_visible.bit(x)
                         && self.forward_merged[forward_i]
-                            == self.forward_merged[forward_i + FORWARD_STRIDE_Y]
-                        && voxel_opt != chunk.voxels[i_3d + STRIDE_Y_3D]
+                            == self.forward_merged[forward_i + Self::FORWARD_STRIDE_Y]
+                        && voxel_opt == chunk.voxels[i_3d + Shape3d::<S>::STRIDE_Y]
+                        && shading == face_shading(chunk, i_3d + Shape3d::<S>::STRIDE_Y, f, lod)
                     {
                         self.forward_merged[forward_i] = 0;
                         self.upward_merged[upward_i] += 1;
@@ -184,7 +203,7 @@ impl InnerMesher {
                     }
 
                     // finish
-                    quads.push({
+                    quads[Pass::of(voxel)].push({
                         let forward_merged = self.forward_merged[forward_i] as u32;
                         let upward_merged = self.upward_merged[upward_i] as u32;
 
@@ -196,13 +215,9 @@ impl InnerMesher {
 
                         let pos = uvec3(x, y, z).as_ivec3() + origin;
 
-                        // TODO: change placeholder
-                        let t = match voxel {
-                            Voxel::Liquid => 0,
-                            Voxel::Solid => 1,
-                        };
+                        let t = texture(voxel);
 
-                        Quad::new(pos, w, h, f, t)
+                        Quad::new(pos, w, h, f, t, shading, voxel.is_transparent())
                     });
 
                     self.forward_merged[forward_i] = 0;
@@ -211,41 +226,46 @@ impl InnerMesher {
             }
         }
 
-        quads.sort_unstable_by_key(|q| q.pos.x);
+        for quads in quads.values_mut() {
+            quads.sort_unstable_by_key(|q| q.pos.x);
+        }
     }
 
     fn merge_y(
         &mut self,
-        chunk: &Chunk,
+        chunk: &Chunk<S>,
         origin: IVec3,
         ys: impl Iterator<Item = u32> + Clone,
         f: Face,
-        quads: &mut Vec<Quad>,
+        quads: &mut EnumMap<Pass, Vec<Quad>>,
     ) {
+        let lod = self.lod;
         let visible_mask = &mut self.visible_masks[f];
 
-        for z in 1..LEN_U32 - 1 {
+        for z in 1..S::LEN_U32 - 1 {
             for y in ys.clone() {
-                let i_2d = [y, z].i_2d();
+                let i_2d = [y, z].i_2d::<S>();
                 let mut visible = visible_mask[i_2d];
 
-                let forward_visible = visible_mask[i_2d + STRIDE_Z_2D];
+                let forward_visible = visible_mask[i_2d + Shape2d::<S>::STRIDE_Z];
 
-                while visible != 0 {
+                while visible != S::Row::ZERO {
                     let x = visible.trailing_zeros();
 
-                    let forward_i = [x, y].i_2d();
+                    let forward_i = [x, y].i_2d::<S>();
 
-                    let i_3d = (x, i_2d).i_3d();
+                    let i_3d = (x, i_2d).i_3d::<S>();
                     let voxel_opt = chunk.voxels[i_3d];
                     let voxel = voxel_opt.unwrap();
+                    let shading = face_shading(chunk, i_3d, f, lod);
 
                     // forward merging
-                    if (forward_visible >> x) & 1 != 0
-                        && voxel_opt == chunk.voxels[i_3d + STRIDE_Y_3D]
+                    if forward_visible.bit(x)
+                        && voxel_opt == chunk.voxels[i_3d + Shape3d::<S>::STRIDE_Z]
+                        && shading == face_shading(chunk, i_3d + Shape3d::<S>::STRIDE_Z, f, lod)
                     {
                         self.forward_merged[forward_i] += 1;
-                        visible &= visible - 1;
+                        visible &= visible - S::Row::ONE;
                         continue;
                     }
 
@@ -253,13 +273,14 @@ impl InnerMesher {
                     let mut right_merged = 1;
                     let mut forward_next_i = forward_i;
                     let mut next_i_3d = i_3d;
-                    for x in x + 1..LEN_U32 - 1 {
-                        forward_next_i += FORWARD_STRIDE_X;
-                        next_i_3d += STRIDE_X_3D;
+                    for x in x + 1..S::LEN_U32 - 1 {
+                        forward_next_i += Self::FORWARD_STRIDE_X;
+                        next_i_3d += Shape3d::<S>::STRIDE_X;
 
-                        if (visible >> x) & 1 == 0
+                        if !visible.bit(x)
                             || self.forward_merged[forward_i] != self.forward_merged[forward_next_i]
                             || voxel_opt != chunk.voxels[next_i_3d]
+                            || shading != face_shading(chunk, next_i_3d, f, lod)
                         {
                             break;
                         }
@@ -267,10 +288,10 @@ impl InnerMesher {
                         right_merged += 1;
                     }
                     let cleared = x + right_merged;
-                    visible &= !((1 << cleared) - 1);
+                    visible &= !((S::Row::ONE << cleared) - S::Row::ONE);
 
                     // finish
-                    quads.push({
+                    quads[Pass::of(voxel)].push({
                         let forward_merged = self.forward_merged[forward_i] as u32;
 
                         let w = right_merged;
@@ -280,12 +301,9 @@ impl InnerMesher {
 
                         let pos = uvec3(x, y, z).as_ivec3() + origin;
 
-                        let t = match voxel {
-                            Voxel::Liquid => 0,
-                            Voxel::Solid => 1,
-                        };
+                        let t = texture(voxel);
 
-                        Quad::new(pos, w, h, f, t)
+                        Quad::new(pos, w, h, f, t, shading, voxel.is_transparent())
                     });
 
                     self.forward_merged[forward_i] = 0
@@ -293,40 +311,45 @@ impl InnerMesher {
             }
         }
 
-        quads.sort_unstable_by_key(|q| q.pos.y);
+        for quads in quads.values_mut() {
+            quads.sort_unstable_by_key(|q| q.pos.y);
+        }
     }
 
     fn merge_z(
         &mut self,
-        chunk: &Chunk,
+        chunk: &Chunk<S>,
         origin: IVec3,
         zs: impl Iterator<Item = u32>,
         f: Face,
-        quads: &mut Vec<Quad>,
+        quads: &mut EnumMap<Pass, Vec<Quad>>,
     ) {
+        let lod = self.lod;
         let visible_mask = &mut self.visible_masks[f];
         for z in zs {
-            for y in 1..LEN_U32 - 1 {
-                let i_2d = [y, z].i_2d();
+            for y in 1..S::LEN_U32 - 1 {
+                let i_2d = [y, z].i_2d::<S>();
                 let mut visible = visible_mask[i_2d];
 
-                let upward_visible = visible_mask[i_2d + STRIDE_Y_2D];
+                let upward_visible = visible_mask[i_2d + Shape2d::<S>::STRIDE_Y];
 
-                while visible != 0 {
+                while visible != S::Row::ZERO {
                     let x = visible.trailing_zeros();
 
                     let upward_i = x as usize;
 
-                    let i_3d = (x, i_2d).i_3d();
+                    let i_3d = (x, i_2d).i_3d::<S>();
                     let voxel_opt = chunk.voxels[i_3d];
                     let voxel = voxel_opt.unwrap();
+                    let shading = face_shading(chunk, i_3d, f, lod);
 
                     // upward merging
-                    if (upward_visible >> x) & 1 != 0
-                        && voxel_opt == chunk.voxels[i_3d + STRIDE_Y_3D]
+                    if upward_visible.bit(x)
+                        && voxel_opt == chunk.voxels[i_3d + Shape3d::<S>::STRIDE_Y]
+                        && shading == face_shading(chunk, i_3d + Shape3d::<S>::STRIDE_Y, f, lod)
                     {
                         self.upward_merged[upward_i] += 1;
-                        visible &= visible - 1;
+                        visible &= visible - S::Row::ONE;
                         continue;
                     }
 
@@ -334,13 +357,14 @@ impl InnerMesher {
                     let mut right_merged = 1;
                     let mut upward_next_i = upward_i;
                     let mut next_i_3d = i_3d;
-                    for x in x + 1..LEN_U32 - 1 {
-                        upward_next_i += UPWARD_STRIDE_X;
-                        next_i_3d += STRIDE_X_3D;
+                    for x in x + 1..S::LEN_U32 - 1 {
+                        upward_next_i += Self::UPWARD_STRIDE_X;
+                        next_i_3d += Shape3d::<S>::STRIDE_X;
 
-                        if (visible >> x) & 1 == 0
+                        if !visible.bit(x)
                             || self.upward_merged[upward_i] != self.upward_merged[upward_next_i]
                             || voxel_opt != chunk.voxels[next_i_3d]
+                            || shading != face_shading(chunk, next_i_3d, f, lod)
                         {
                             break;
                         }
@@ -348,10 +372,10 @@ impl InnerMesher {
                         right_merged += 1;
                     }
                     let cleared = x + right_merged;
-                    visible &= !((1 << cleared) - 1);
+                    visible &= !((S::Row::ONE << cleared) - S::Row::ONE);
 
                     // finish
-                    quads.push({
+                    quads[Pass::of(voxel)].push({
                         let upward_merged = self.upward_merged[upward_i] as u32;
 
                         let w = right_merged;
@@ -361,12 +385,9 @@ impl InnerMesher {
 
                         let pos = uvec3(x, y, z).as_ivec3() + origin;
 
-                        let t = match voxel {
-                            Voxel::Liquid => 0,
-                            Voxel::Solid => 1,
-                        };
+                        let t = texture(voxel);
 
-                        Quad::new(pos, w, h, f, t)
+                        Quad::new(pos, w, h, f, t, shading, voxel.is_transparent())
                     });
 
                     self.upward_merged[upward_i] = 0;
@@ -375,43 +396,33 @@ impl InnerMesher {
         }
     }
 
-    pub fn mesh(&mut self, chunk: &Chunk, origin: IVec3) -> ChunkMesh {
+    pub fn mesh(&mut self, chunk: &Chunk<S>, origin: IVec3) -> ChunkMesh {
         self.build_all_visible_masks(chunk);
-        ChunkMesh(self.merged_quads(chunk, origin))
+        ChunkMesh::new(self.merged_quads(chunk, origin))
     }
 }
 
-impl Mesher {
+impl<S: ChunkSize> Mesher<S> {
     pub fn remesh(
         &mut self,
-        chunk: &Chunk,
+        chunk: &Chunk<S>,
         origin: IVec3,
         mesh: &mut ChunkMesh,
         changes: ChunkMeshChanges,
     ) {
-        let remesh = changes.0.map(|x| (x | x << 1 | x >> 1) & !PAD_MASK);
+        let unpadded = (!S::Row::PAD).widen();
+        let remesh = expand::<S>(changes, self.lod)
+            .rows
+            .map(|x| (x | x << 1 | x >> 1) & unpadded);
 
         self.build_visible_masks(chunk, remesh);
 
         for f in Face::ALL {
-            let quads = &mut mesh[f];
-            match f {
+            let (slices, slice_origin, key): (u64, i32, fn(&Quad) -> i32) = match f {
                 PosX | NegX => {
-                    self.inner
-                        .merge_x(chunk, origin, remesh.x, f, &mut self.quads);
-
-                    let mut src_start = 0;
-                    for x in BitIter::from(remesh.x).map(|x| origin.x + x as i32) {
-                        let dst_range = key_range(&quads, |q| q.pos.x, x);
-
-                        let src_end = self.quads.partition_point(|q| q.pos.x <= x);
-                        let replace_with = self.quads[src_start..src_end].iter().copied();
-                        src_start = src_end;
-
-                        quads.splice(dst_range, replace_with);
-                    }
-
-                    self.quads.clear();
+                    let xs = S::Row::truncate(remesh.x);
+                    self.inner.merge_x(chunk, origin, xs, f, &mut self.quads);
+                    (remesh.x, origin.x, |q| q.pos.x)
                 }
                 PosY | NegY => {
                     self.inner.merge_y(
@@ -421,19 +432,7 @@ impl Mesher {
                         f,
                         &mut self.quads,
                     );
-
-                    let mut src_start = 0;
-                    for y in BitIter::from(remesh.y).map(|y| origin.y + y as i32) {
-                        let dst_range = key_range(&quads, |q| q.pos.y, y);
-
-                        let src_end = self.quads.partition_point(|q| q.pos.y <= y);
-                        let replace_with = self.quads[src_start..src_end].iter().copied();
-                        src_start = src_end;
-
-                        quads.splice(dst_range, replace_with);
-                    }
-
-                    self.quads.clear();
+                    (remesh.y, origin.y, |q| q.pos.y)
                 }
                 PosZ | NegZ => {
                     self.inner.merge_z(
@@ -443,25 +442,136 @@ impl Mesher {
                         f,
                         &mut self.quads,
                     );
+                    (remesh.z, origin.z, |q| q.pos.z)
+                }
+            };
+
+            for pass in Pass::ALL {
+                let quads = &mut mesh.quads[pass][f];
+                let new = &mut self.quads[pass];
 
-                    let mut src_start = 0;
-                    for z in BitIter::from(remesh.z).map(|z| origin.z + z as i32) {
-                        let dst_range = key_range(&quads, |q| q.pos.z, z);
+                let mut src_start = 0;
+                for k in BitIter::from(slices).map(|k| slice_origin + k as i32) {
+                    let dst_range = key_range(quads, key, k);
 
-                        let src_end = self.quads.partition_point(|q| q.pos.z <= z);
-                        let replace_with = self.quads[src_start..src_end].iter().copied();
-                        src_start = src_end;
+                    let src_end = new.partition_point(|q| key(q) <= k);
+                    let replace_with = &new[src_start..src_end];
+                    src_start = src_end;
 
-                        quads.splice(dst_range, replace_with);
+                    if dst_range.is_empty() && replace_with.is_empty() {
+                        continue;
                     }
+                    mesh.dirty[pass][f] = true;
 
-                    self.quads.clear();
+                    quads.splice(dst_range, replace_with.iter().copied());
                 }
+
+                new.clear();
             }
         }
     }
 }
 
+/// Ambient occlusion of the 4 corners of face `f` of voxel `i_3d`, 2 bits each from 0 (darkest)
+/// to 3 (unoccluded).
+///
+/// Corner `c` is on the positive side of the face's first tangent axis if bit 0 of `c` is set and
+/// of its second if bit 1 is, tangents being Y and Z for X faces, X and Z for Y faces and X and Y
+/// for Z faces
+#[inline]
+fn ambient_occlusion<S: ChunkSize>(masks: &Masks<S>, i_3d: usize, f: Face) -> u32 {
+    let (normal, a, b) = axes::<S>(f);
+    let occupied = |offset: isize| masks.is_some(i_3d.wrapping_add_signed(normal + offset)) as u32;
+
+    let mut ao = 0;
+    for c in 0..4 {
+        let a = if c & 1 != 0 { a } else { -a };
+        let b = if c & 2 != 0 { b } else { -b };
+
+        let side_a = occupied(a);
+        let side_b = occupied(b);
+        let corner = occupied(a + b);
+
+        let value = if side_a + side_b == 2 {
+            0
+        } else {
+            3 - side_a - side_b - corner
+        };
+        ao |= value << (c * 2);
+    }
+    ao
+}
+
+/// Every corner at 3
+const UNOCCLUDED: u32 = MAX8;
+
+/// Strides of the normal and the two tangents of face `f`
+#[inline]
+fn axes<S: ChunkSize>(f: Face) -> (isize, isize, isize) {
+    let x = Shape3d::<S>::STRIDE_X as isize;
+    let y = Shape3d::<S>::STRIDE_Y as isize;
+    let z = Shape3d::<S>::STRIDE_Z as isize;
+
+    match f {
+        PosX => (x, y, z),
+        NegX => (-x, y, z),
+        PosY => (y, x, z),
+        NegY => (-y, x, z),
+        PosZ => (z, x, y),
+        NegZ => (-z, x, y),
+    }
+}
+
+/// Ambient occlusion and light of face `f` of voxel `i_3d`, fully lit if the chunk has no light.
+///
+/// Collapsed chunks skip the occlusion, it would split the faces of a block apart
+#[inline]
+fn face_shading<S: ChunkSize>(chunk: &Chunk<S>, i_3d: usize, f: Face, lod: Lod) -> Shading {
+    let (normal, _, _) = axes::<S>(f);
+    let front = i_3d.wrapping_add_signed(normal);
+
+    Shading {
+        ao: if lod == Lod::Full {
+            ambient_occlusion(&chunk.masks, i_3d, f)
+        } else {
+            UNOCCLUDED
+        },
+        light: chunk
+            .light
+            .as_ref()
+            .map_or(MAX_LIGHT, |light| light.level(front)) as u32,
+    }
+}
+
+/// Bits of row `i_2d` whose `f` face isn't hidden by the neighbouring voxel.
+///
+/// Opaque faces are hidden by opaque neighbours. Transparent faces are hidden by any neighbour
+/// except a transparent one of the other liquid-ness, so water-water is culled but water-ice isn't
+#[inline]
+fn unculled<S: ChunkSize>(masks: &Masks<S>, i_2d: usize, f: Face) -> S::Row {
+    let adj = |mask: &Mask<S>| match f {
+        PosX => mask[i_2d] >> 1,
+        NegX => mask[i_2d] << 1,
+        PosY => mask[i_2d + Shape2d::<S>::STRIDE_Y],
+        NegY => mask[i_2d - Shape2d::<S>::STRIDE_Y],
+        PosZ => mask[i_2d + Shape2d::<S>::STRIDE_Z],
+        NegZ => mask[i_2d - Shape2d::<S>::STRIDE_Z],
+    };
+
+    let transparent = masks.transparent_mask[i_2d];
+    let liquid = masks.liquid_mask()[i_2d];
+
+    let adj_some = adj(masks.some_mask());
+    let adj_transparent = adj(&masks.transparent_mask);
+    let adj_liquid = adj(masks.liquid_mask());
+    let adj_opaque = adj_some & !adj_transparent;
+
+    let opaque_visible = !transparent & !adj_opaque;
+    let transparent_visible = transparent & (!adj_some | (adj_transparent & (liquid ^ adj_liquid)));
+
+    opaque_visible | transparent_visible
+}
+
 #[inline]
 fn key_range(slice: &[Quad], key: impl Fn(&Quad) -> i32, k: i32) -> Range<usize> {
     let start = slice.partition_point(|q| key(q) < k);
@@ -470,7 +580,132 @@ fn key_range(slice: &[Quad], key: impl Fn(&Quad) -> i32, k: i32) -> Range<usize>
     start..end
 }
 
+/// Texture of `voxel`, must match the `TEXTURE_*` ids in `quad.wgsl`
+#[inline]
+fn texture(voxel: Voxel) -> u32 {
+    match voxel {
+        Voxel::Liquid => 0,
+        Voxel::Solid => 1,
+        Voxel::Lava => 2,
+        Voxel::Ice => 3,
+    }
+}
+
 #[inline]
 fn u32(usize: usize) -> u32 {
     usize as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Size64;
+    use crate::chunk::size::Size32;
+
+    /// A solid `2..4`³ cube in otherwise empty air
+    fn cube(p: [u32; 3]) -> Option<Voxel> {
+        p.iter().all(|c| (2..4).contains(c)).then_some(Voxel::Solid)
+    }
+
+    fn meshes_cube_into_one_quad_per_face<S: ChunkSize>() {
+        let chunk = Chunk::<S>::from_fn(cube);
+        let mesh = Mesher::<S>::default().mesh(&chunk, IVec3::ZERO);
+
+        for f in Face::ALL {
+            assert_eq!(mesh[Pass::Opaque][f].len(), 1, "{f:?}");
+            assert!(mesh[Pass::Transparent][f].is_empty(), "{f:?}");
+        }
+    }
+
+    fn remeshes_like_a_full_mesh<S: ChunkSize>() {
+        let mut mesher = Mesher::<S>::default();
+        let mut chunk = Chunk::<S>::default();
+        let mut mesh = mesher.mesh(&chunk, IVec3::ZERO);
+
+        let mut changes = ChunkMeshChanges::default();
+        for z in 0..S::LEN_U32 {
+            for y in 0..S::LEN_U32 {
+                for x in 0..S::LEN_U32 {
+                    if let Some(v) = cube([x, y, z]) {
+                        chunk.set([x, y, z], Some(v));
+                        changes.push::<S>([x, y, z]);
+                    }
+                }
+            }
+        }
+        mesher.remesh(&chunk, IVec3::ZERO, &mut mesh, changes);
+
+        assert!(mesh.quads == mesher.mesh(&chunk, IVec3::ZERO).quads);
+    }
+
+    /// Mesh of `voxels` placed in otherwise empty air
+    fn mesh_of(voxels: &[([u32; 3], Voxel)]) -> ChunkMesh {
+        let mut chunk = Chunk::<Size32>::default();
+        for &(p, v) in voxels {
+            chunk.set(p, Some(v));
+        }
+        Mesher::<Size32>::default().mesh(&chunk, IVec3::ZERO)
+    }
+
+    #[test]
+    fn culls_only_between_the_same_liquidness() {
+        // water on a solid floor: the floor's top is drawn behind the water, the water's bottom
+        // is hidden by the floor
+        let mesh = mesh_of(&[([2, 2, 2], Voxel::Solid), ([2, 3, 2], Voxel::Liquid)]);
+        for f in Face::ALL {
+            assert_eq!(mesh[Pass::Opaque][f].len(), 1, "{f:?}");
+            let transparent = if f == NegY { 0 } else { 1 };
+            assert_eq!(mesh[Pass::Transparent][f].len(), transparent, "{f:?}");
+        }
+
+        // water against water: only the outer sides
+        let mesh = mesh_of(&[([2, 2, 2], Voxel::Liquid), ([3, 2, 2], Voxel::Liquid)]);
+        assert_eq!(mesh[Pass::Transparent][PosX].len(), 1);
+        assert_eq!(mesh[Pass::Transparent][NegX].len(), 1);
+
+        // water against ice: both sides of where they meet
+        let mesh = mesh_of(&[([2, 2, 2], Voxel::Liquid), ([3, 2, 2], Voxel::Ice)]);
+        assert_eq!(mesh[Pass::Transparent][PosX].len(), 2);
+        assert_eq!(mesh[Pass::Transparent][NegX].len(), 2);
+    }
+
+    #[test]
+    fn never_merges_different_voxels() {
+        for (axis, d) in [(0, [1, 0, 0]), (1, [0, 1, 0]), (2, [0, 0, 1])] {
+            let next = [2 + d[0], 2 + d[1], 2 + d[2]];
+            let mesh = mesh_of(&[([2, 2, 2], Voxel::Solid), (next, Voxel::Lava)]);
+
+            for f in Face::ALL {
+                if f.normal()[axis] == 0 {
+                    assert_eq!(mesh[Pass::Opaque][f].len(), 2, "{f:?} along {d:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lights_faces_looking_into_the_padding() {
+        // ground everywhere but the padding below x, which is open to the sky
+        let mut chunk =
+            Chunk::<Size32>::from_fn(|[x, y, _]| (x >= 1 && y < 4).then_some(Voxel::Solid));
+        chunk.enable_light(|_| true);
+        let mesh = Mesher::<Size32>::default().mesh(&chunk, IVec3::ZERO);
+
+        assert!(!mesh[Pass::Opaque][NegX].is_empty());
+        for quad in &mesh[Pass::Opaque][NegX] {
+            assert!((quad.other >> LIGHT_SHIFT) & MAX4 > 0, "{quad:?}");
+        }
+    }
+
+    #[test]
+    fn meshes_size32() {
+        meshes_cube_into_one_quad_per_face::<Size32>();
+        remeshes_like_a_full_mesh::<Size32>();
+    }
+
+    #[test]
+    fn meshes_size64() {
+        meshes_cube_into_one_quad_per_face::<Size64>();
+        remeshes_like_a_full_mesh::<Size64>();
+    }
+}
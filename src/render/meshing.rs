@@ -0,0 +1,179 @@
+use bevy::prelude::*;
+use bevy::tasks::futures::check_ready;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use std::time::Instant;
+
+use super::lod::{ChunkLod, CollapsedChunk};
+use super::mesher::MESHER;
+use super::water::{ChunkWater, SmoothWater, water_mesh};
+use super::{ChunkMesh, ChunkMeshChanges};
+use crate::chunk::BoxChunk;
+use crate::chunk::map::ChunkPos;
+
+/// Most meshes applied in a frame, the rest stay in their tasks until the next one
+pub const MAX_APPLIED_PER_FRAME: usize = 4;
+
+/// What a meshing job hands back
+pub struct MeshResult {
+    mesh: ChunkMesh,
+    /// Smooth water surface, `None` if no voxel changed and `Some(None)` if off or empty
+    water: Option<Option<Mesh>>,
+}
+
+struct MeshTask {
+    /// When the job was spawned, finished jobs are applied oldest first
+    spawned: Instant,
+    task: Task<MeshResult>,
+}
+
+/// Meshing job of a chunk in flight, at most one so changes made meanwhile wait for the next
+#[derive(Component)]
+pub struct MeshTasks {
+    task: Option<MeshTask>,
+    /// Changes since the snapshot of the last job, all remeshed by the next one
+    unapplied: ChunkMeshChanges,
+    /// Whether the next job meshes the whole chunk
+    full: bool,
+}
+
+impl Default for MeshTasks {
+    /// Nothing meshed yet
+    fn default() -> Self {
+        Self {
+            task: None,
+            unapplied: ChunkMeshChanges::default(),
+            full: true,
+        }
+    }
+}
+
+/// Spawns a job with a snapshot of every idle chunk that changed since its last one. Chunks
+/// still meshing keep their changes for when the job is applied
+#[allow(clippy::type_complexity)]
+pub fn spawn_mesh_tasks(
+    mut chunks: Query<(
+        &BoxChunk,
+        &ChunkPos,
+        &ChunkMesh,
+        &mut ChunkMeshChanges,
+        &mut MeshTasks,
+        Ref<ChunkLod>,
+        &CollapsedChunk,
+    )>,
+    smooth_water: Res<SmoothWater>,
+) {
+    let pool = AsyncComputeTaskPool::get();
+
+    for (chunk, pos, mesh, mut changes, mut tasks, lod, collapsed) in &mut chunks {
+        tasks.full |= smooth_water.is_changed() || lod.is_changed();
+        if !changes.is_empty() {
+            tasks.unapplied.merge(*changes);
+            changes.clear();
+        }
+
+        if tasks.task.is_some() || (tasks.unapplied.is_empty() && !tasks.full) {
+            continue;
+        }
+
+        let snapshot = match &collapsed.0 {
+            Some(collapsed) => {
+                let mut snapshot = collapsed.snapshot();
+                snapshot.flow = chunk.flow.clone();
+                snapshot
+            }
+            None => chunk.snapshot(),
+        };
+        let origin = pos.origin();
+        let smooth_water = **smooth_water;
+        let full = std::mem::take(&mut tasks.full);
+        // meshing on top of the applied mesh only redoes what changed since
+        let mut base = (!full).then(|| ChunkMesh::new(mesh.quads.clone()));
+        let unapplied = std::mem::take(&mut tasks.unapplied);
+        let lod = *lod;
+
+        let task = pool.spawn(async move {
+            let mesh = MESHER.with_borrow_mut(|mesher| {
+                mesher.smooth_water = smooth_water;
+                mesher.lod = lod.lod;
+
+                match &mut base {
+                    Some(base) => {
+                        mesher.remesh(&snapshot, origin, base, unapplied);
+                        base.quads.clone()
+                    }
+                    None => mesher.mesh(&snapshot, origin).quads,
+                }
+            });
+            // light changes alone leave the surface as it is
+            let water = (full || unapplied.voxels).then(|| {
+                smooth_water
+                    .then(|| water_mesh(&snapshot, origin))
+                    .filter(|water| water.count_vertices() > 0)
+            });
+
+            MeshResult {
+                mesh: ChunkMesh::new(mesh),
+                water,
+            }
+        });
+
+        tasks.task = Some(MeshTask {
+            spawned: Instant::now(),
+            task,
+        });
+    }
+}
+
+/// Applies finished jobs oldest first, up to [`MAX_APPLIED_PER_FRAME`] a frame so no chunk waits
+/// behind ones that keep remeshing
+pub fn apply_mesh_tasks(
+    mut chunks: Query<(&mut ChunkMesh, &mut MeshTasks, &ChunkWater)>,
+    water: Query<Option<&Mesh3d>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    let mut finished: Vec<_> = chunks
+        .iter_mut()
+        .filter(|(_, tasks, _)| tasks.task.as_ref().is_some_and(|t| t.task.is_finished()))
+        .collect();
+    finished.sort_by_key(|(_, tasks, _)| tasks.task.as_ref().unwrap().spawned);
+
+    for (mut mesh, mut tasks, chunk_water) in finished.into_iter().take(MAX_APPLIED_PER_FRAME) {
+        let mut finished = tasks.task.take().unwrap();
+        let result = check_ready(&mut finished.task).unwrap();
+
+        // only slices that differ are uploaded, along with those dirtied since the last extraction
+        let mut new = result.mesh;
+        for (pass, faces) in &mut new.dirty {
+            for (f, dirty) in faces {
+                *dirty = mesh.dirty[pass][f] || new.quads[pass][f] != mesh.quads[pass][f];
+            }
+        }
+        *mesh = new;
+
+        // empty meshes are removed rather than uploaded, unchanged ones are kept
+        match (water.get(**chunk_water), result.water) {
+            (_, None) => {}
+            (Ok(Some(mesh_3d)), Some(Some(surface))) => {
+                if let Some(mesh) = meshes.get_mut(&mesh_3d.0) {
+                    *mesh = surface;
+                }
+            }
+            (_, Some(Some(surface))) => {
+                commands
+                    .entity(**chunk_water)
+                    .insert(Mesh3d(meshes.add(surface)));
+            }
+            (_, Some(None)) => {
+                commands.entity(**chunk_water).remove::<Mesh3d>();
+            }
+        }
+    }
+}
+
+/// Forgets the slices of meshes that changed last frame, they've been extracted since
+pub fn clear_dirty_slices(mut meshes: Query<&mut ChunkMesh, Changed<ChunkMesh>>) {
+    for mut mesh in &mut meshes {
+        mesh.bypass_change_detection().dirty = default();
+    }
+}
@@ -1,6 +1,10 @@
+pub mod lod;
 pub mod mesher;
+pub mod meshing;
 pub mod pipeline;
+pub mod water;
 
+use bevy::camera::primitives::Aabb;
 use bevy::{math::U64Vec3, prelude::*};
 use bit_iter::BitIter;
 use bytemuck::{Pod, Zeroable};
@@ -9,42 +13,70 @@ use enum_map::{Enum, EnumMap};
 pub use Face::*;
 
 use crate::chunk::index::Index3d;
+use crate::chunk::map::ChunkPos;
+use crate::chunk::{ChunkSize, DefaultSize, Size64, Voxel};
 
-const MAX6: u32 = (1 << 6) - 1;
-const MAX16: u32 = u16::MAX as u32;
+/// Layout of [`Quad`], must match `QUAD_VERSION` in `quad.wgsl`
+pub const QUAD_VERSION: u32 = 2;
 
+const MAX4: u32 = (1 << 4) - 1;
+const MAX8: u32 = u8::MAX as u32;
+const MAX12: u32 = (1 << 12) - 1;
+
+// other
+const FACE_SHIFT: u32 = 0;
+const TRANSPARENT_SHIFT: u32 = 3;
+const LIGHT_SHIFT: u32 = 4;
+const TEXTURE_SHIFT: u32 = 8;
+
+// size_ao
 const WIDTH_SHIFT: u32 = 0;
-const HEIGHT_SHIFT: u32 = 6;
-const FACE_SHIFT: u32 = 12;
-// skip 1
-const TEXTURE_SHIFT: u32 = 16;
+const HEIGHT_SHIFT: u32 = 12;
+const AO_SHIFT: u32 = 24;
+
+// a quad may span a whole chunk of the largest size, padding included
+const _: () = assert!(Size64::LEN_U32 <= MAX12, "chunks too long for quad sizes");
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
 pub struct Quad {
     pub pos: IVec3,
     other: u32,
+    size_ao: u32,
 }
 
 impl Quad {
     #[inline]
-    pub fn new(pos: IVec3, w: u32, h: u32, f: Face, t: u32) -> Self {
-        debug_assert!(w <= MAX6, "width: {w} > {MAX6}");
-        debug_assert!(h <= MAX6, "height: {h} > {MAX6}");
-        debug_assert!(t <= MAX16, "texture: {t} > {MAX16}");
+    pub fn new(pos: IVec3, w: u32, h: u32, f: Face, t: u32, s: Shading, transparent: bool) -> Self {
+        debug_assert!(w <= MAX12, "width: {w} > {MAX12}");
+        debug_assert!(h <= MAX12, "height: {h} > {MAX12}");
+        debug_assert!(t <= MAX8, "texture: {t} > {MAX8}");
+        debug_assert!(s.ao <= MAX8, "ao: {} > {MAX8}", s.ao);
+        debug_assert!(s.light <= MAX4, "light: {} > {MAX4}", s.light);
 
         let f = f as u32;
+        let transparent = transparent as u32;
 
         Self {
             pos,
-            other: (w << WIDTH_SHIFT)
-                | (h << HEIGHT_SHIFT)
-                | (f << FACE_SHIFT)
+            other: (f << FACE_SHIFT)
+                | (transparent << TRANSPARENT_SHIFT)
+                | (s.light << LIGHT_SHIFT)
                 | (t << TEXTURE_SHIFT),
+            size_ao: (w << WIDTH_SHIFT) | (h << HEIGHT_SHIFT) | (s.ao << AO_SHIFT),
         }
     }
 }
 
+/// Baked lighting of a quad, faces are only merged when theirs is equal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shading {
+    /// 2 bits per corner, see `mesher::ambient_occlusion`
+    pub ao: u32,
+    /// Light level of the voxel in front of the face
+    pub light: u32,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Enum)]
 pub enum Face {
@@ -58,42 +90,114 @@ pub enum Face {
 
 impl Face {
     const ALL: [Self; 6] = [PosX, PosY, PosZ, NegX, NegY, NegZ];
-}
 
-#[derive(Component, Deref, DerefMut)]
-pub struct ChunkMesh(pub EnumMap<Face, Vec<Quad>>);
+    #[inline]
+    pub fn normal(self) -> IVec3 {
+        match self {
+            PosX => IVec3::X,
+            PosY => IVec3::Y,
+            PosZ => IVec3::Z,
+            NegX => IVec3::NEG_X,
+            NegY => IVec3::NEG_Y,
+            NegZ => IVec3::NEG_Z,
+        }
+    }
 
-impl ChunkMesh {
+    /// Whether any face of this direction inside `min..max` can face `eye`
     #[inline]
-    pub fn len(&self) -> usize {
-        self.0.values().map(|vec| vec.len()).sum()
+    pub fn is_visible_from(self, eye: Vec3, min: Vec3, max: Vec3) -> bool {
+        match self {
+            PosX => eye.x > min.x,
+            PosY => eye.y > min.y,
+            PosZ => eye.z > min.z,
+            NegX => eye.x < max.x,
+            NegY => eye.y < max.y,
+            NegZ => eye.z < max.z,
+        }
     }
+}
+
+/// Which render phase a quad is drawn in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Enum)]
+pub enum Pass {
+    Opaque,
+    Transparent,
+}
+
+impl Pass {
+    const ALL: [Self; 2] = [Pass::Opaque, Pass::Transparent];
 
     #[inline]
-    pub fn quads(&self) -> impl Iterator<Item = &Quad> {
-        self.0.values().flat_map(|v| v.iter())
+    pub fn of(voxel: Voxel) -> Self {
+        if voxel.is_transparent() {
+            Pass::Transparent
+        } else {
+            Pass::Opaque
+        }
     }
 }
 
+#[derive(Component, Deref, DerefMut)]
+pub struct ChunkMesh {
+    #[deref]
+    pub quads: EnumMap<Pass, EnumMap<Face, Vec<Quad>>>,
+    /// Slices changed since the last extraction, only these are uploaded again
+    pub dirty: EnumMap<Pass, EnumMap<Face, bool>>,
+}
+
+impl ChunkMesh {
+    pub fn new(quads: EnumMap<Pass, EnumMap<Face, Vec<Quad>>>) -> Self {
+        Self {
+            quads,
+            dirty: EnumMap::from_fn(|_| EnumMap::from_fn(|_| true)),
+        }
+    }
+}
+
+/// Bounds of every quad the chunk can mesh, padding is never meshed
+pub fn chunk_aabb(pos: ChunkPos) -> Aabb {
+    let origin = pos.origin().as_vec3();
+    Aabb::from_min_max(origin + 1., origin + (DefaultSize::LEN - 1) as f32)
+}
+
+/// Rows of each axis with changed voxels or light
 #[derive(Component, Default, Clone, Copy)]
-pub struct ChunkMeshChanges(pub U64Vec3);
+pub struct ChunkMeshChanges {
+    pub rows: U64Vec3,
+    /// Whether any voxel changed rather than only light, the water surface ignores light
+    pub voxels: bool,
+}
 
 impl ChunkMeshChanges {
     #[inline]
     pub fn clear(&mut self) {
-        self.0 = U64Vec3::ZERO
+        *self = Self::default()
     }
 
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.0 == U64Vec3::ZERO
+        self.rows == U64Vec3::ZERO
+    }
+
+    /// Voxel `p` of a chunk of size `S` changed
+    #[inline]
+    pub fn push<S: ChunkSize>(&mut self, p: impl Index3d) {
+        self.push_light::<S>(p);
+        self.voxels = true;
+    }
+
+    /// Only the light at `p` changed
+    #[inline]
+    pub fn push_light<S: ChunkSize>(&mut self, p: impl Index3d) {
+        let [x, y, z] = p.xyz::<S>();
+        self.rows.x |= 1 << x;
+        self.rows.y |= 1 << y;
+        self.rows.z |= 1 << z;
     }
 
     #[inline]
-    pub fn push(&mut self, p: impl Index3d) {
-        let [x, y, z] = p.xyz();
-        self.0.x |= 1 << x;
-        self.0.y |= 1 << y;
-        self.0.z |= 1 << z;
+    pub fn merge(&mut self, other: Self) {
+        self.rows |= other.rows;
+        self.voxels |= other.voxels;
     }
 }
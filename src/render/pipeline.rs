@@ -1,37 +1,52 @@
 use bevy::asset::{embedded_asset, load_embedded_asset};
-use bevy::core_pipeline::core_3d::Transparent3d;
+use bevy::core_pipeline::core_3d::{Opaque3d, Opaque3dBatchSetKey, Opaque3dBinKey, Transparent3d};
+use bevy::core_pipeline::prepass::{
+    DeferredPrepass, DepthPrepass, MotionVectorPrepass, NormalPrepass, Opaque3dPrepass,
+    OpaqueNoLightmap3dBatchSetKey, OpaqueNoLightmap3dBinKey,
+};
+use bevy::ecs::component::Tick;
 use bevy::ecs::query::QueryItem;
 use bevy::ecs::system::{
     SystemParamItem,
     lifetimeless::{Read, SRes},
 };
 use bevy::image::{ImageAddressMode, ImageLoaderSettings};
+use bevy::light::ShadowFilteringMethod;
 use bevy::mesh::{MeshVertexBufferLayoutRef, VertexBufferLayout, VertexFormat};
 use bevy::pbr::{
-    MeshPipeline, MeshPipelineKey, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup,
-    SetMeshViewBindingArrayBindGroup,
+    ExtractedDirectionalLight, LightEntity, MeshPipeline, MeshPipelineKey, PrepassPipeline,
+    RenderCascadesVisibleEntities, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup,
+    SetMeshViewBindingArrayBindGroup, SetPrepassViewBindGroup, Shadow, ShadowBatchSetKey,
+    ShadowBinKey, ViewLightEntities, init_prepass_pipeline,
 };
 use bevy::prelude::*;
 use bevy::render::extract_component::{ExtractComponent, ExtractComponentPlugin};
 use bevy::render::mesh::{RenderMesh, RenderMeshBufferInfo, allocator::MeshAllocator};
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_phase::{
-    AddRenderCommand, DrawFunctions, PhaseItem, PhaseItemExtraIndex, RenderCommand,
-    RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewSortedRenderPhases,
+    AddRenderCommand, BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, PhaseItem,
+    PhaseItemExtraIndex, RenderCommand, RenderCommandResult, SetItemPipeline, TrackedRenderPass,
+    ViewBinnedRenderPhases, ViewSortedRenderPhases,
 };
 use bevy::render::render_resource::{
-    AsBindGroup, BindGroup, BindGroupLayout, Buffer, BufferInitDescriptor, BufferUsages,
-    PipelineCache, RenderPipelineDescriptor, SpecializedMeshPipeline, SpecializedMeshPipelineError,
-    SpecializedMeshPipelines, VertexAttribute, VertexStepMode,
+    AsBindGroup, BindGroup, BindGroupLayout, Buffer, BufferDescriptor, BufferUsages,
+    CommandEncoderDescriptor, CompareFunction, PipelineCache, RenderPipelineDescriptor,
+    SpecializedMeshPipeline, SpecializedMeshPipelineError, SpecializedMeshPipelines,
+    VertexAttribute, VertexStepMode,
 };
-use bevy::render::renderer::RenderDevice;
+use bevy::render::renderer::{RenderDevice, RenderQueue};
 use bevy::render::storage::GpuShaderStorageBuffer;
 use bevy::render::sync_world::MainEntity;
 use bevy::render::texture::{FallbackImage, GpuImage};
-use bevy::render::view::ExtractedView;
+use bevy::render::view::{ExtractedView, RenderVisibleEntities};
 use bevy::render::{Render, RenderApp, RenderStartup, RenderSystems};
+use bevy::shader::ShaderDefVal;
+use enum_map::EnumMap;
+use std::mem::offset_of;
+use std::ops::Range;
 
-use super::{ChunkMesh, Quad};
+use super::{ChunkMesh, Face, Pass, QUAD_VERSION, Quad, chunk_aabb};
+use crate::chunk::map::ChunkPos;
 
 pub struct QuadInstancingPlugin;
 
@@ -44,13 +59,23 @@ impl Plugin for QuadInstancingPlugin {
 
         app.sub_app_mut(RenderApp)
             .init_resource::<ArrayTextureBindGroup>()
-            .add_render_command::<Transparent3d, DrawFunction>()
+            .add_render_command::<Opaque3d, DrawFunction>()
+            .add_render_command::<Transparent3d, DrawTransparentFunction>()
+            .add_render_command::<Opaque3dPrepass, DrawPrepassFunction>()
+            .add_render_command::<Shadow, DrawShadowFunction>()
             .init_resource::<SpecializedMeshPipelines<QuadInstancingPipeline>>()
-            .add_systems(RenderStartup, (init_custom_pipeline, init_material))
+            .add_systems(
+                RenderStartup,
+                (
+                    init_custom_pipeline.after(init_prepass_pipeline),
+                    init_material,
+                ),
+            )
             .add_systems(
                 Render,
                 (
-                    queue_quads.in_set(RenderSystems::QueueMeshes),
+                    (queue_quads, queue_quad_prepass, queue_quad_shadows)
+                        .in_set(RenderSystems::QueueMeshes),
                     prepare_instance_buffers.in_set(RenderSystems::PrepareResources),
                     prepare_bind_group.in_set(RenderSystems::PrepareBindGroups),
                 ),
@@ -63,18 +88,24 @@ struct QuadInstancingPipeline {
     shader: Handle<Shader>,
     mesh_pipeline: MeshPipeline,
     layout: BindGroupLayout,
+    /// View layout of the depth only passes, which have no lights or mesh uniforms bound
+    prepass_view_layout: BindGroupLayout,
+    depth_clip_control_supported: bool,
 }
 
 fn init_custom_pipeline(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mesh_pipeline: Res<MeshPipeline>,
+    prepass_pipeline: Res<PrepassPipeline>,
     render_device: Res<RenderDevice>,
 ) {
     commands.insert_resource(QuadInstancingPipeline {
         shader: load_embedded_asset!(&*asset_server, "quad.wgsl"),
         mesh_pipeline: mesh_pipeline.clone(),
         layout: ArrayTextureMaterial::bind_group_layout(&render_device),
+        prepass_view_layout: prepass_pipeline.view_layout_no_motion_vectors.clone(),
+        depth_clip_control_supported: prepass_pipeline.depth_clip_control_supported,
     });
 }
 
@@ -89,70 +120,146 @@ impl SpecializedMeshPipeline for QuadInstancingPipeline {
         let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
 
         descriptor.vertex.shader = self.shader.clone();
+        let quad_version = ShaderDefVal::UInt("QUAD_VERSION".into(), QUAD_VERSION);
+        descriptor.vertex.shader_defs.push(quad_version.clone());
+        if let Some(fragment) = &mut descriptor.fragment {
+            fragment.shader_defs.push(quad_version);
+        }
         descriptor.vertex.buffers.push(VertexBufferLayout {
             array_stride: size_of::<Quad>() as u64,
             step_mode: VertexStepMode::Instance,
             attributes: vec![
                 VertexAttribute {
                     format: VertexFormat::Sint32x3,
-                    offset: 0,
+                    offset: offset_of!(Quad, pos) as u64,
                     shader_location: 8,
                 },
                 VertexAttribute {
                     format: VertexFormat::Uint32,
-                    offset: VertexFormat::Sint32x3.size(),
+                    offset: offset_of!(Quad, other) as u64,
                     shader_location: 9,
                 },
+                VertexAttribute {
+                    format: VertexFormat::Uint32,
+                    offset: offset_of!(Quad, size_ao) as u64,
+                    shader_location: 10,
+                },
             ],
         });
-        descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
-        descriptor.layout.push(self.layout.clone());
+
+        if key.contains(MeshPipelineKey::DEPTH_PREPASS) {
+            // same instance expansion, but only the position is needed
+            descriptor.label = Some("quad depth pipeline".into());
+            descriptor.vertex.entry_point = Some("depth_vertex".into());
+            descriptor.fragment = None;
+            descriptor.layout = vec![self.prepass_view_layout.clone()];
+
+            // the sun's cascades would otherwise clip casters between it and their near plane
+            if key.contains(MeshPipelineKey::UNCLIPPED_DEPTH_ORTHO) {
+                if self.depth_clip_control_supported {
+                    descriptor.primitive.unclipped_depth = true;
+                } else {
+                    descriptor
+                        .vertex
+                        .shader_defs
+                        .push("UNCLIPPED_DEPTH_ORTHO_EMULATION".into());
+                }
+            }
+            if let Some(depth_stencil) = &mut descriptor.depth_stencil {
+                depth_stencil.depth_write_enabled = true;
+                depth_stencil.depth_compare = CompareFunction::GreaterEqual;
+            }
+        } else {
+            descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
+            descriptor.layout.push(self.layout.clone());
+        }
         Ok(descriptor)
     }
 }
 
-#[derive(Component, ExtractComponent, Clone, Deref, DerefMut, Default)]
-pub struct ChunkQuads(Vec<Quad>);
+type Slices<T> = EnumMap<Pass, EnumMap<Face, T>>;
+
+/// Slices that changed since the last extraction, `None` if unchanged
+#[derive(Component, ExtractComponent, Clone, Default)]
+pub struct ChunkQuads {
+    slices: Slices<Option<Vec<Quad>>>,
+    /// For sorting and culling face groups the camera is behind
+    min: Vec3,
+    max: Vec3,
+}
 
 impl ExtractComponent for ChunkMesh {
     type Out = ChunkQuads;
-    type QueryData = &'static Self;
-    type QueryFilter = ();
-
-    fn extract_component(mesh: QueryItem<'_, '_, Self::QueryData>) -> Option<Self::Out> {
-        let mut out = ChunkQuads(Vec::with_capacity(mesh.len()));
-        for q in mesh.quads() {
-            out.push(*q);
-        }
-        Some(out)
+    type QueryData = (&'static Self, &'static ChunkPos);
+    type QueryFilter = Changed<Self>;
+
+    fn extract_component((mesh, pos): QueryItem<'_, '_, Self::QueryData>) -> Option<Self::Out> {
+        let aabb = chunk_aabb(*pos);
+        Some(ChunkQuads {
+            slices: EnumMap::from_fn(|pass| {
+                EnumMap::from_fn(|f| mesh.dirty[pass][f].then(|| mesh[pass][f].clone()))
+            }),
+            min: aabb.min().into(),
+            max: aabb.max().into(),
+        })
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn queue_quads(
+    opaque_3d_draw_functions: Res<DrawFunctions<Opaque3d>>,
     transparent_3d_draw_functions: Res<DrawFunctions<Transparent3d>>,
     custom_pipeline: Res<QuadInstancingPipeline>,
     mut pipelines: ResMut<SpecializedMeshPipelines<QuadInstancingPipeline>>,
     pipeline_cache: Res<PipelineCache>,
     meshes: Res<RenderAssets<RenderMesh>>,
     render_mesh_instances: Res<RenderMeshInstances>,
-    material_meshes: Query<(Entity, &MainEntity), With<ChunkQuads>>,
+    mesh_allocator: Res<MeshAllocator>,
+    material_meshes: Query<&ChunkQuads>,
+    mut opaque_render_phases: ResMut<ViewBinnedRenderPhases<Opaque3d>>,
     mut transparent_render_phases: ResMut<ViewSortedRenderPhases<Transparent3d>>,
-    views: Query<(&ExtractedView, &Msaa)>,
+    views: Query<(
+        &ExtractedView,
+        &RenderVisibleEntities,
+        &Msaa,
+        Has<DistanceFog>,
+        Option<&ShadowFilteringMethod>,
+    )>,
+    mut next_tick: Local<Tick>,
 ) {
-    let draw_custom = transparent_3d_draw_functions.read().id::<DrawFunction>();
-
-    for (view, msaa) in &views {
-        let Some(transparent_phase) = transparent_render_phases.get_mut(&view.retained_view_entity)
-        else {
+    let draw_opaque = opaque_3d_draw_functions.read().id::<DrawFunction>();
+    let draw_transparent = transparent_3d_draw_functions
+        .read()
+        .id::<DrawTransparentFunction>();
+
+    for (view, visible_entities, msaa, fog, shadow_filter) in &views {
+        let (Some(opaque_phase), Some(transparent_phase)) = (
+            opaque_render_phases.get_mut(&view.retained_view_entity),
+            transparent_render_phases.get_mut(&view.retained_view_entity),
+        ) else {
             continue;
         };
 
         let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
 
-        let view_key: MeshPipelineKey = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+        let mut view_key: MeshPipelineKey = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+        if fog {
+            view_key |= MeshPipelineKey::DISTANCE_FOG;
+        }
+        view_key |= match shadow_filter.copied().unwrap_or_default() {
+            ShadowFilteringMethod::Hardware2x2 => {
+                MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2
+            }
+            ShadowFilteringMethod::Gaussian => MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN,
+            ShadowFilteringMethod::Temporal => MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL,
+        };
         let rangefinder = view.rangefinder3d();
-        for (entity, main_entity) in &material_meshes {
-            let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(*main_entity)
+        // frustum culled against each chunk's aabb
+        for &(entity, main_entity) in visible_entities.iter::<Mesh3d>() {
+            let Ok(quads) = material_meshes.get(entity) else {
+                continue;
+            };
+            let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(main_entity)
             else {
                 continue;
             };
@@ -162,14 +269,46 @@ fn queue_quads(
             let key =
                 view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology());
 
-            let pipeline = pipelines
+            let opaque_pipeline = pipelines
                 .specialize(&pipeline_cache, &custom_pipeline, key, &mesh.layout)
                 .unwrap();
+            let transparent_pipeline = pipelines
+                .specialize(
+                    &pipeline_cache,
+                    &custom_pipeline,
+                    key | MeshPipelineKey::BLEND_ALPHA,
+                    &mesh.layout,
+                )
+                .unwrap();
+
+            // bump the tick so the bin is rebuilt
+            let this_tick = next_tick.get() + 1;
+            next_tick.set(this_tick);
+
+            let (vertex_slab, index_slab) = mesh_allocator.mesh_slabs(&mesh_instance.mesh_asset_id);
+            opaque_phase.add(
+                Opaque3dBatchSetKey {
+                    pipeline: opaque_pipeline,
+                    draw_function: draw_opaque,
+                    material_bind_group_index: None,
+                    vertex_slab: vertex_slab.unwrap_or_default(),
+                    index_slab,
+                    lightmap_slab: None,
+                },
+                Opaque3dBinKey {
+                    asset_id: mesh_instance.mesh_asset_id.untyped(),
+                },
+                (entity, main_entity),
+                InputUniformIndex::default(),
+                BinnedRenderPhaseType::NonMesh,
+                *next_tick,
+            );
+
             transparent_phase.add(Transparent3d {
-                entity: (entity, *main_entity),
-                pipeline,
-                draw_function: draw_custom,
-                distance: rangefinder.distance_translation(&mesh_instance.translation),
+                entity: (entity, main_entity),
+                pipeline: transparent_pipeline,
+                draw_function: draw_transparent,
+                distance: rangefinder.distance_translation(&((quads.min + quads.max) / 2.)),
                 batch_range: 0..1,
                 extra_index: PhaseItemExtraIndex::None,
                 indexed: true,
@@ -178,27 +317,309 @@ fn queue_quads(
     }
 }
 
+type DepthOnlyPrepass = (
+    With<DepthPrepass>,
+    Without<NormalPrepass>,
+    Without<MotionVectorPrepass>,
+    Without<DeferredPrepass>,
+);
+
+/// Opaque quads into the depth prepass of views that only have depth, the extra targets of the
+/// other prepasses would need a fragment shader
+#[allow(clippy::too_many_arguments)]
+fn queue_quad_prepass(
+    prepass_draw_functions: Res<DrawFunctions<Opaque3dPrepass>>,
+    custom_pipeline: Res<QuadInstancingPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<QuadInstancingPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<RenderMesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    mesh_allocator: Res<MeshAllocator>,
+    material_meshes: Query<(), With<ChunkQuads>>,
+    mut prepass_render_phases: ResMut<ViewBinnedRenderPhases<Opaque3dPrepass>>,
+    views: Query<(&ExtractedView, &RenderVisibleEntities, &Msaa), DepthOnlyPrepass>,
+    mut next_tick: Local<Tick>,
+) {
+    let draw_prepass = prepass_draw_functions.read().id::<DrawPrepassFunction>();
+
+    for (view, visible_entities, msaa) in &views {
+        let Some(prepass_phase) = prepass_render_phases.get_mut(&view.retained_view_entity) else {
+            continue;
+        };
+
+        let view_key =
+            MeshPipelineKey::from_msaa_samples(msaa.samples()) | MeshPipelineKey::DEPTH_PREPASS;
+        for &(entity, main_entity) in visible_entities.iter::<Mesh3d>() {
+            if !material_meshes.contains(entity) {
+                continue;
+            }
+            let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(main_entity)
+            else {
+                continue;
+            };
+            let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else {
+                continue;
+            };
+            let key =
+                view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology());
+            let pipeline = pipelines
+                .specialize(&pipeline_cache, &custom_pipeline, key, &mesh.layout)
+                .unwrap();
+
+            let this_tick = next_tick.get() + 1;
+            next_tick.set(this_tick);
+
+            let (vertex_slab, index_slab) = mesh_allocator.mesh_slabs(&mesh_instance.mesh_asset_id);
+            prepass_phase.add(
+                OpaqueNoLightmap3dBatchSetKey {
+                    pipeline,
+                    draw_function: draw_prepass,
+                    material_bind_group_index: None,
+                    vertex_slab: vertex_slab.unwrap_or_default(),
+                    index_slab,
+                },
+                OpaqueNoLightmap3dBinKey {
+                    asset_id: mesh_instance.mesh_asset_id.untyped(),
+                },
+                (entity, main_entity),
+                InputUniformIndex::default(),
+                BinnedRenderPhaseType::NonMesh,
+                *next_tick,
+            );
+        }
+    }
+}
+
+/// Chunks into the shadow cascades of directional lights, the only lights the world has
+#[allow(clippy::too_many_arguments)]
+fn queue_quad_shadows(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    custom_pipeline: Res<QuadInstancingPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<QuadInstancingPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<RenderMesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    mesh_allocator: Res<MeshAllocator>,
+    material_meshes: Query<(), With<ChunkQuads>>,
+    mut shadow_render_phases: ResMut<ViewBinnedRenderPhases<Shadow>>,
+    view_lights: Query<(Entity, &ViewLightEntities), With<ExtractedView>>,
+    view_light_entities: Query<(&LightEntity, &ExtractedView)>,
+    directional_lights: Query<&RenderCascadesVisibleEntities, With<ExtractedDirectionalLight>>,
+    mut next_tick: Local<Tick>,
+) {
+    let draw_shadow = shadow_draw_functions.read().id::<DrawShadowFunction>();
+
+    for (view, view_lights) in &view_lights {
+        for &view_light in &view_lights.lights {
+            let Ok((
+                &LightEntity::Directional {
+                    light_entity,
+                    cascade_index,
+                },
+                light_view,
+            )) = view_light_entities.get(view_light)
+            else {
+                continue;
+            };
+            let Some(shadow_phase) = shadow_render_phases.get_mut(&light_view.retained_view_entity)
+            else {
+                continue;
+            };
+            // culled against the cascade's frustum
+            let Some(visible_entities) = directional_lights
+                .get(light_entity)
+                .ok()
+                .and_then(|cascades| cascades.entities.get(&view))
+                .and_then(|cascades| cascades.get(cascade_index))
+            else {
+                continue;
+            };
+
+            for &(entity, main_entity) in visible_entities.iter() {
+                if !material_meshes.contains(entity) {
+                    continue;
+                }
+                let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(main_entity)
+                else {
+                    continue;
+                };
+                let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else {
+                    continue;
+                };
+                let key = MeshPipelineKey::DEPTH_PREPASS
+                    | MeshPipelineKey::UNCLIPPED_DEPTH_ORTHO
+                    | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology());
+                let pipeline = pipelines
+                    .specialize(&pipeline_cache, &custom_pipeline, key, &mesh.layout)
+                    .unwrap();
+
+                let this_tick = next_tick.get() + 1;
+                next_tick.set(this_tick);
+
+                let (vertex_slab, index_slab) =
+                    mesh_allocator.mesh_slabs(&mesh_instance.mesh_asset_id);
+                shadow_phase.add(
+                    ShadowBatchSetKey {
+                        pipeline,
+                        draw_function: draw_shadow,
+                        material_bind_group_index: None,
+                        vertex_slab: vertex_slab.unwrap_or_default(),
+                        index_slab,
+                    },
+                    ShadowBinKey {
+                        asset_id: mesh_instance.mesh_asset_id.untyped(),
+                    },
+                    (entity, main_entity),
+                    InputUniformIndex::default(),
+                    BinnedRenderPhaseType::NonMesh,
+                    *next_tick,
+                );
+            }
+        }
+    }
+}
+
+const QUAD_SIZE: u64 = size_of::<Quad>() as u64;
+
+/// Smallest number of instances reserved for a slice
+const MIN_SLICE_CAPACITY: u32 = 64;
+
+/// Instances of one slice, with room to grow in place
+#[derive(Clone, Copy, Default)]
+struct Slice {
+    offset: u32,
+    len: u32,
+    capacity: u32,
+}
+
+impl Slice {
+    #[inline]
+    fn instances(&self) -> Range<u32> {
+        self.offset..self.offset + self.len
+    }
+}
+
+/// Persistent per-chunk buffer, every slice has its own region so a remesh only rewrites the
+/// slices it changed
 #[derive(Component)]
 struct InstanceBuffer {
     buffer: Buffer,
-    length: usize,
+    slices: Slices<Slice>,
+}
+
+impl InstanceBuffer {
+    /// Lays out a buffer big enough for `changed`, copying the unchanged slices over from `old`
+    fn new(
+        old: Option<&Self>,
+        changed: &Slices<Option<Vec<Quad>>>,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) -> Self {
+        let mut slices = Slices::<Slice>::default();
+        let mut offset = 0;
+
+        for (pass, faces) in &mut slices {
+            for (f, slice) in faces {
+                let old_len = old.map_or(0, |old| old.slices[pass][f].len);
+                let len = changed[pass][f]
+                    .as_ref()
+                    .map_or(old_len, |q| q.len() as u32);
+                let capacity = (len + len / 2).max(MIN_SLICE_CAPACITY);
+
+                *slice = Slice {
+                    offset,
+                    len: old_len,
+                    capacity,
+                };
+                offset += capacity;
+            }
+        }
+
+        let buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("instance data buffer"),
+            size: offset as u64 * QUAD_SIZE,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        if let Some(old) = old {
+            let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("instance data copy"),
+            });
+            for (pass, faces) in &slices {
+                for (f, slice) in faces {
+                    if changed[pass][f].is_some() || slice.len == 0 {
+                        continue;
+                    }
+                    encoder.copy_buffer_to_buffer(
+                        &old.buffer,
+                        old.slices[pass][f].offset as u64 * QUAD_SIZE,
+                        &buffer,
+                        slice.offset as u64 * QUAD_SIZE,
+                        slice.len as u64 * QUAD_SIZE,
+                    );
+                }
+            }
+            render_queue.submit([encoder.finish()]);
+        }
+
+        Self { buffer, slices }
+    }
+
+    fn fits(&self, changed: &Slices<Option<Vec<Quad>>>) -> bool {
+        changed.iter().all(|(pass, faces)| {
+            faces.iter().all(|(f, quads)| {
+                quads
+                    .as_ref()
+                    .is_none_or(|q| q.len() as u32 <= self.slices[pass][f].capacity)
+            })
+        })
+    }
+
+    /// Uploads the changed slices, taking them out of `changed`
+    fn write(&mut self, changed: &mut Slices<Option<Vec<Quad>>>, render_queue: &RenderQueue) {
+        for (pass, faces) in changed {
+            for (f, quads) in faces {
+                let Some(quads) = quads.take() else {
+                    continue;
+                };
+                let slice = &mut self.slices[pass][f];
+                slice.len = quads.len() as u32;
+
+                if !quads.is_empty() {
+                    render_queue.write_buffer(
+                        &self.buffer,
+                        slice.offset as u64 * QUAD_SIZE,
+                        bytemuck::cast_slice(&quads),
+                    );
+                }
+            }
+        }
+    }
 }
 
 fn prepare_instance_buffers(
     mut commands: Commands,
-    query: Query<(Entity, &ChunkQuads)>,
+    mut query: Query<(Entity, &mut ChunkQuads, Option<&mut InstanceBuffer>), Changed<ChunkQuads>>,
     render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
 ) {
-    for (entity, quads) in &query {
-        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("instance data buffer"),
-            contents: bytemuck::cast_slice(quads),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-        });
-        commands.entity(entity).insert(InstanceBuffer {
-            buffer,
-            length: quads.len(),
-        });
+    for (entity, mut quads, instance_buffer) in &mut query {
+        match instance_buffer {
+            Some(mut instance_buffer) if instance_buffer.fits(&quads.slices) => {
+                instance_buffer.write(&mut quads.slices, &render_queue);
+            }
+            old => {
+                let mut instance_buffer = InstanceBuffer::new(
+                    old.as_deref(),
+                    &quads.slices,
+                    &render_device,
+                    &render_queue,
+                );
+                instance_buffer.write(&mut quads.slices, &render_queue);
+                commands.entity(entity).insert(instance_buffer);
+            }
+        }
     }
 }
 
@@ -236,7 +657,7 @@ fn prepare_bind_group(
     fallback_image: Res<FallbackImage>,
     gpu_shader_storage_buffer: Res<RenderAssets<GpuShaderStorageBuffer>>,
 ) {
-    if let None = bind_group.0 {
+    if bind_group.0.is_none() {
         bind_group.0 = material
             .as_bind_group(
                 &pipeline.layout,
@@ -253,27 +674,48 @@ type DrawFunction = (
     SetMeshViewBindGroup<0>,
     SetMeshViewBindingArrayBindGroup<1>,
     SetMeshBindGroup<2>,
-    DrawMeshInstanced,
+    DrawMeshInstanced<false>,
+);
+
+type DrawTransparentFunction = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshViewBindingArrayBindGroup<1>,
+    SetMeshBindGroup<2>,
+    DrawMeshInstanced<true>,
+);
+
+type DrawPrepassFunction = (
+    SetItemPipeline,
+    SetPrepassViewBindGroup<0>,
+    DrawDepthInstanced<false>,
+);
+
+type DrawShadowFunction = (
+    SetItemPipeline,
+    SetPrepassViewBindGroup<0>,
+    DrawDepthInstanced<true>,
 );
 
-struct DrawMeshInstanced;
+/// Draws either the opaque or the transparent instances of a chunk, one draw per visible face group
+struct DrawMeshInstanced<const TRANSPARENT: bool>;
 
-impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+impl<P: PhaseItem, const TRANSPARENT: bool> RenderCommand<P> for DrawMeshInstanced<TRANSPARENT> {
     type Param = (
         SRes<RenderAssets<RenderMesh>>,
         SRes<RenderMeshInstances>,
         SRes<MeshAllocator>,
         SRes<ArrayTextureBindGroup>,
     );
-    type ViewQuery = ();
-    type ItemQuery = Read<InstanceBuffer>;
+    type ViewQuery = Read<ExtractedView>;
+    type ItemQuery = (Read<InstanceBuffer>, Read<ChunkQuads>);
 
     #[inline]
     fn render<'w>(
         // It seems this function is never getting called.
         item: &P,
-        _view: (),
-        instance_buffer: Option<&'w InstanceBuffer>,
+        view: &'w ExtractedView,
+        item_query: Option<(&'w InstanceBuffer, &'w ChunkQuads)>,
         (meshes, render_mesh_instances, mesh_allocator, bind_group): SystemParamItem<
             'w,
             '_,
@@ -281,61 +723,141 @@ impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
         >,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        // A borrow check workaround.
-        let mesh_allocator = mesh_allocator.into_inner();
-
-        let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(item.main_entity())
-        else {
-            return RenderCommandResult::Skip;
-        };
-        let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id) else {
+        let Some((instance_buffer, quads)) = item_query else {
             return RenderCommandResult::Skip;
         };
 
-        let Some(instance_buffer) = instance_buffer else {
-            return RenderCommandResult::Skip;
+        let quad_pass = if TRANSPARENT {
+            Pass::Transparent
+        } else {
+            Pass::Opaque
         };
-
-        if instance_buffer.length == 0 {
+        // a face group is skipped whole when the camera is behind every quad in it
+        let eye = view.world_from_view.translation();
+        let slices = instance_buffer.slices[quad_pass]
+            .iter()
+            .filter(|&(f, slice)| slice.len > 0 && f.is_visible_from(eye, quads.min, quads.max))
+            .map(|(_, slice)| slice);
+
+        if slices.clone().next().is_none() {
             return RenderCommandResult::Skip;
         }
         let Some(bind_group) = &bind_group.into_inner().0 else {
             return RenderCommandResult::Skip;
         };
+        pass.set_bind_group(3, bind_group, &[]);
+
+        draw_slices(
+            item.main_entity(),
+            instance_buffer,
+            slices,
+            (meshes, render_mesh_instances, mesh_allocator),
+            pass,
+        )
+    }
+}
+
+/// Draws every face group of a chunk into a depth only pass, transparent quads too when casting
+/// shadows since light doesn't get through them unharmed either
+struct DrawDepthInstanced<const WITH_TRANSPARENT: bool>;
 
-        let Some(vertex_buffer_slice) =
-            mesh_allocator.mesh_vertex_slice(&mesh_instance.mesh_asset_id)
-        else {
+impl<P: PhaseItem, const WITH_TRANSPARENT: bool> RenderCommand<P>
+    for DrawDepthInstanced<WITH_TRANSPARENT>
+{
+    type Param = (
+        SRes<RenderAssets<RenderMesh>>,
+        SRes<RenderMeshInstances>,
+        SRes<MeshAllocator>,
+    );
+    type ViewQuery = ();
+    type ItemQuery = Read<InstanceBuffer>;
+
+    #[inline]
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        instance_buffer: Option<&'w InstanceBuffer>,
+        param: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(instance_buffer) = instance_buffer else {
             return RenderCommandResult::Skip;
         };
 
-        pass.set_vertex_buffer(0, vertex_buffer_slice.buffer.slice(..));
-        pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+        let passes: &[Pass] = if WITH_TRANSPARENT {
+            &Pass::ALL
+        } else {
+            &[Pass::Opaque]
+        };
+        let slices = passes
+            .iter()
+            .flat_map(|&quad_pass| instance_buffer.slices[quad_pass].values())
+            .filter(|slice| slice.len > 0);
+
+        if slices.clone().next().is_none() {
+            return RenderCommandResult::Skip;
+        }
 
-        pass.set_bind_group(3, bind_group, &[]);
+        draw_slices(item.main_entity(), instance_buffer, slices, param, pass)
+    }
+}
 
-        match &gpu_mesh.buffer_info {
-            RenderMeshBufferInfo::Indexed {
-                index_format,
-                count,
-            } => {
-                let Some(index_buffer_slice) =
-                    mesh_allocator.mesh_index_slice(&mesh_instance.mesh_asset_id)
-                else {
-                    return RenderCommandResult::Skip;
-                };
+/// One instanced draw of the chunk's quad mesh per slice
+#[inline]
+fn draw_slices<'w, 'a>(
+    main_entity: MainEntity,
+    instance_buffer: &'w InstanceBuffer,
+    slices: impl Iterator<Item = &'a Slice>,
+    (meshes, render_mesh_instances, mesh_allocator): (
+        Res<'w, RenderAssets<RenderMesh>>,
+        Res<'w, RenderMeshInstances>,
+        Res<'w, MeshAllocator>,
+    ),
+    pass: &mut TrackedRenderPass<'w>,
+) -> RenderCommandResult {
+    // A borrow check workaround.
+    let mesh_allocator = mesh_allocator.into_inner();
+
+    let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(main_entity) else {
+        return RenderCommandResult::Skip;
+    };
+    let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id) else {
+        return RenderCommandResult::Skip;
+    };
+
+    let Some(vertex_buffer_slice) = mesh_allocator.mesh_vertex_slice(&mesh_instance.mesh_asset_id)
+    else {
+        return RenderCommandResult::Skip;
+    };
+
+    pass.set_vertex_buffer(0, vertex_buffer_slice.buffer.slice(..));
+    pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+
+    match &gpu_mesh.buffer_info {
+        RenderMeshBufferInfo::Indexed {
+            index_format,
+            count,
+        } => {
+            let Some(index_buffer_slice) =
+                mesh_allocator.mesh_index_slice(&mesh_instance.mesh_asset_id)
+            else {
+                return RenderCommandResult::Skip;
+            };
 
-                pass.set_index_buffer(index_buffer_slice.buffer.slice(..), 0, *index_format);
+            pass.set_index_buffer(index_buffer_slice.buffer.slice(..), 0, *index_format);
+            for slice in slices {
                 pass.draw_indexed(
                     index_buffer_slice.range.start..(index_buffer_slice.range.start + count),
                     vertex_buffer_slice.range.start as i32,
-                    0..instance_buffer.length as u32,
+                    slice.instances(),
                 );
             }
-            RenderMeshBufferInfo::NonIndexed => {
-                pass.draw(vertex_buffer_slice.range, 0..instance_buffer.length as u32);
+        }
+        RenderMeshBufferInfo::NonIndexed => {
+            for slice in slices {
+                pass.draw(vertex_buffer_slice.range.clone(), slice.instances());
             }
         }
-        RenderCommandResult::Success
     }
+    RenderCommandResult::Success
 }
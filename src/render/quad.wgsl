@@ -1,32 +1,104 @@
 #import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_clip}
 
-const MASK6: u32 = (1 << 6) - 1;
+// layout of the instances, bumped together with `QUAD_VERSION` in `render/mod.rs`
+const QUAD_VERSION: u32 = 2;
+const_assert QUAD_VERSION == #{QUAD_VERSION};
+
+const MASK2: u32 = (1 << 2) - 1;
 const MASK3: u32 = (1 << 3) - 1;
+const MASK4: u32 = (1 << 4) - 1;
+const MASK8: u32 = (1 << 8) - 1;
+const MASK12: u32 = (1 << 12) - 1;
+
+// other
+const FACE_SHIFT: u32 = 0;
+const TRANSPARENT_SHIFT: u32 = 3;
+const LIGHT_SHIFT: u32 = 4;
+const TEXTURE_SHIFT: u32 = 8;
 
+// size_ao
 const WIDTH_SHIFT: u32 = 0;
-const HEIGHT_SHIFT: u32 = 6;
-const FACE_SHIFT: u32 = 12;
-// skip 1
-const TEXTURE_SHIFT: u32 = 16;
+const HEIGHT_SHIFT: u32 = 12;
+const AO_SHIFT: u32 = 24;
+
+const TRANSPARENT_ALPHA: f32 = 0.6;
+// brightness of a fully occluded corner
+const AO_MIN: f32 = 0.4;
+const MAX_LIGHT: u32 = 15;
+// brightness kept per light level below max
+const LIGHT_FALLOFF: f32 = 0.8;
+
+// texture ids, see `texture` in `mesher.rs`. Lava and ice have no layers of their own in the
+// texture array yet, they tint the stone and water ones
+const TEXTURE_WATER: u32 = 0;
+const TEXTURE_STONE: u32 = 1;
+const TEXTURE_LAVA: u32 = 2;
+const TEXTURE_ICE: u32 = 3;
+
+const LAVA_TINT: vec3<f32> = vec3(1.0, 0.35, 0.05);
+// lava glows the same whether lit or not
+const LAVA_GLOW: f32 = 1.5;
+const ICE_TINT: vec3<f32> = vec3(0.85, 0.95, 1.0);
+
+fn texture_layer(texture: u32) -> u32 {
+    switch(texture) {
+        case TEXTURE_LAVA: {
+            return TEXTURE_STONE;
+        }
+        case TEXTURE_ICE: {
+            return TEXTURE_WATER;
+        }
+        default: {
+            return texture;
+        }
+    }
+}
 
-fn instance_width(other: u32) -> u32 {
-    return (other >> WIDTH_SHIFT) & MASK6;
+fn texture_tint(texture: u32) -> vec3<f32> {
+    switch(texture) {
+        case TEXTURE_LAVA: {
+            return LAVA_TINT;
+        }
+        case TEXTURE_ICE: {
+            return ICE_TINT;
+        }
+        default: {
+            return vec3(1.0);
+        }
+    }
+}
+
+fn instance_width(size_ao: u32) -> u32 {
+    return (size_ao >> WIDTH_SHIFT) & MASK12;
 }
 
-fn instance_height(other: u32) -> u32 {
-    return (other >> HEIGHT_SHIFT) & MASK6;
+fn instance_height(size_ao: u32) -> u32 {
+    return (size_ao >> HEIGHT_SHIFT) & MASK12;
 }
 
-fn instance_size(other: u32) -> vec2<u32> {
-    return vec2<u32>(instance_width(other), instance_height(other));
+fn instance_size(size_ao: u32) -> vec2<u32> {
+    return vec2<u32>(instance_width(size_ao), instance_height(size_ao));
 }
 
 fn instance_face(other: u32) -> u32 {
     return (other >> FACE_SHIFT) & MASK3;
 }
 
+fn instance_transparent(other: u32) -> bool {
+    return ((other >> TRANSPARENT_SHIFT) & 1u) != 0u;
+}
+
+// ao of corner `c` of the quad, see `corner`
+fn instance_ao(size_ao: u32, c: u32) -> u32 {
+    return (size_ao >> (AO_SHIFT + c * 2u)) & MASK2;
+}
+
+fn instance_light(other: u32) -> u32 {
+    return (other >> LIGHT_SHIFT) & MASK4;
+}
+
 fn instance_texture(other: u32) -> u32 {
-    return other >> TEXTURE_SHIFT;
+    return (other >> TEXTURE_SHIFT) & MASK8;
 }
 
 const POS_X: u32 = 0;
@@ -43,6 +115,7 @@ struct Vertex {
 
     @location(8) instance_position: vec3<i32>,
     @location(9) instance_other: u32,
+    @location(10) instance_size_ao: u32,
 };
 
 struct VertexOutput {
@@ -52,11 +125,31 @@ struct VertexOutput {
     @location(2) uv: vec2<f32>,
 
     @location(8) @interpolate(flat) texture: u32,
+    @location(9) @interpolate(flat) alpha: f32,
+    @location(10) ao: f32,
+    @location(11) @interpolate(flat) light: f32,
 }
 
-@vertex
-fn vertex(vertex: Vertex) -> VertexOutput {
-    let instance_size = instance_size(vertex.instance_other);
+// bit 0 is set on the positive side of the face's first tangent axis and bit 1 on the positive
+// side of its second, tangents being yz for x faces, xz for y faces and xy for z faces
+fn corner(face: u32, local: vec3<f32>) -> u32 {
+    var tangents: vec2<f32>;
+    switch(face) {
+        case POS_X, NEG_X: {
+            tangents = local.yz;
+        }
+        case POS_Y, NEG_Y: {
+            tangents = local.xz;
+        }
+        default: {
+            tangents = local.xy;
+        }
+    }
+    return u32(tangents.x > 0.5) | (u32(tangents.y > 0.5) << 1u);
+}
+
+fn expand(vertex: Vertex) -> VertexOutput {
+    let instance_size = instance_size(vertex.instance_size_ao);
     let instance_face = instance_face(vertex.instance_other);
     let instance_texture = instance_texture(vertex.instance_other);
     
@@ -71,6 +164,7 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 
     out.uv = vertex.uv * s;
     out.texture = instance_texture;
+    out.alpha = select(1.0, TRANSPARENT_ALPHA, instance_transparent(vertex.instance_other));
 
     switch(instance_face) {
         case POS_X: {
@@ -98,6 +192,10 @@ fn vertex(vertex: Vertex) -> VertexOutput {
             out.world_normal = vec3(-n.x, n.y, -n.z);
         }
     }
+    let ao = instance_ao(vertex.instance_size_ao, corner(instance_face, position));
+    out.ao = mix(AO_MIN, 1.0, f32(ao) / 3.0);
+    out.light = pow(LIGHT_FALLOFF, f32(MAX_LIGHT - instance_light(vertex.instance_other)));
+
     position += vec3<f32>(vertex.instance_position);
 
     let world_position = vec4(position, 1.0);
@@ -120,11 +218,29 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     return out;
 }
 
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    return expand(vertex);
+}
+
+// position only, for shadow maps and the depth prepass
+@vertex
+fn depth_vertex(vertex: Vertex) -> @builtin(position) vec4<f32> {
+    var position = expand(vertex).position;
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+    // casters between the sun and the near plane of its cascade are flattened onto it instead
+    // of being clipped
+    position.z = min(position.z, 1.0);
+#endif
+    return position;
+}
+
 #import bevy_pbr::{
-    mesh_view_bindings::view,
+    mesh_view_bindings::{view, fog},
     pbr_types::{PbrInput, pbr_input_new},
     pbr_functions as fns,
     pbr_bindings,
+    mesh_types::MESH_FLAGS_SHADOW_RECEIVER_BIT,
 }
 #import bevy_core_pipeline::tonemapping::tone_mapping
 
@@ -137,8 +253,17 @@ fn fragment(
 ) -> @location(0) vec4<f32> {
     var pbr_input: PbrInput = pbr_input_new();
 
-    pbr_input.material.base_color = textureSample(my_array_texture, my_array_texture_sampler, in.uv, in.texture);
+    let texel = textureSample(my_array_texture, my_array_texture_sampler, in.uv, texture_layer(in.texture));
+    pbr_input.material.base_color = texel * vec4(texture_tint(in.texture), 1.0);
+    pbr_input.material.base_color.a *= in.alpha;
+    pbr_input.material.base_color *= vec4(vec3(in.ao * in.light), 1.0);
+
+    if in.texture == TEXTURE_LAVA {
+        // alpha 0 leaves it out of the exposure so it glows in daylight too
+        pbr_input.material.emissive = vec4(texel.rgb * LAVA_TINT * LAVA_GLOW, 0.0);
+    }
 
+    pbr_input.flags = MESH_FLAGS_SHADOW_RECEIVER_BIT;
     pbr_input.frag_coord = in.position;
     pbr_input.world_position = in.world_position;
     pbr_input.world_normal = in.world_normal;
@@ -149,5 +274,11 @@ fn fragment(
 
     pbr_input.V = fns::calculate_view(in.world_position, pbr_input.is_orthographic);
 
-    return tone_mapping(fns::apply_pbr_lighting(pbr_input), view.color_grading);
+    var color = fns::apply_pbr_lighting(pbr_input);
+
+#ifdef DISTANCE_FOG
+    color = fns::apply_fog(fog, color, in.world_position.xyz, view.world_position.xyz);
+#endif
+
+    return tone_mapping(color, view.color_grading);
 }
@@ -0,0 +1,315 @@
+use bevy::asset::{RenderAssetUsages, embedded_asset};
+use bevy::image::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor};
+use bevy::mesh::{
+    Indices, MeshVertexAttribute, MeshVertexBufferLayoutRef, PrimitiveTopology, VertexFormat,
+};
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+    AsBindGroup, Extent3d, RenderPipelineDescriptor, ShaderType, SpecializedMeshPipelineError,
+    TextureDimension, TextureFormat,
+};
+use bevy::shader::ShaderRef;
+use std::f32::consts::TAU;
+
+use crate::chunk::index::*;
+use crate::chunk::{Chunk, ChunkSize, Row};
+
+pub struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        embedded_asset!(app, "water.wgsl");
+
+        app.add_plugins(MaterialPlugin::<LiquidMaterial> {
+            prepass_enabled: false,
+            // cast with the default prepass shader, so the shadow doesn't follow the waves
+            shadows_enabled: true,
+            ..default()
+        })
+        .init_resource::<SmoothWater>();
+    }
+}
+
+const SHADER_PATH: &str = "embedded://voxel_water/render/water.wgsl";
+
+/// Horizontal flow of the water around each vertex in voxels per tick
+pub const ATTRIBUTE_FLOW: MeshVertexAttribute =
+    MeshVertexAttribute::new("Flow", 988540917, VertexFormat::Float32x2);
+
+/// Whether water is drawn as a smooth surface instead of greedy meshed cubes
+#[derive(Resource, Deref, DerefMut)]
+pub struct SmoothWater(pub bool);
+
+impl Default for SmoothWater {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+#[derive(ShaderType, Debug, Clone)]
+pub struct LiquidSettings {
+    pub color: LinearRgba,
+    /// Height of the waves on upward facing surfaces
+    pub wave_amplitude: f32,
+    pub wave_speed: f32,
+    /// Normal map repeats per voxel
+    pub ripple_scale: f32,
+    /// How far the ripples bend the normal
+    pub ripple_strength: f32,
+    /// Normal map repeats scrolled per second for every voxel per tick of flow
+    pub flow_speed: f32,
+    pub sky_brightness: f32,
+}
+
+impl Default for LiquidSettings {
+    fn default() -> Self {
+        Self {
+            color: Color::srgba(0.1, 0.35, 0.6, 0.7).into(),
+            wave_amplitude: 0.08,
+            wave_speed: 1.5,
+            ripple_scale: 0.125,
+            ripple_strength: 0.15,
+            flow_speed: 0.5,
+            sky_brightness: 1.0,
+        }
+    }
+}
+
+/// Waves, scrolling ripples and a fresnel blend from the water colour to the reflected skybox
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct LiquidMaterial {
+    #[uniform(0)]
+    pub settings: LiquidSettings,
+    #[texture(1, dimension = "cube")]
+    #[sampler(2)]
+    pub skybox: Handle<Image>,
+    #[texture(3)]
+    #[sampler(4)]
+    pub normal_map: Handle<Image>,
+}
+
+impl Material for LiquidMaterial {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.vertex.buffers = vec![layout.0.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+            ATTRIBUTE_FLOW.at_shader_location(2),
+        ])?];
+        // seen from below when underwater
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+}
+
+/// Tileable ripple normals from sines with a whole number of periods across the texture
+pub fn ripple_normal_map() -> Image {
+    const SIZE: u32 = 64;
+    // periods across the texture along x and y, amplitude
+    const WAVES: [(f32, f32, f32); 4] = [
+        (1., 2., 0.3),
+        (3., -1., 0.2),
+        (-2., 5., 0.1),
+        (7., 3., 0.05),
+    ];
+
+    let mut data = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let u = x as f32 / SIZE as f32 * TAU;
+            let v = y as f32 / SIZE as f32 * TAU;
+
+            let mut slope = Vec2::ZERO;
+            for (kx, ky, amplitude) in WAVES {
+                slope += vec2(kx, ky) * amplitude * (kx * u + ky * v).cos();
+            }
+
+            let n = vec3(-slope.x, -slope.y, 1.).normalize() * 0.5 + 0.5;
+            data.extend(n.extend(1.).to_array().map(|c| (c * 255.) as u8));
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: SIZE,
+            height: SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::linear()
+    });
+    image
+}
+
+/// The entity drawing a chunk's water surface
+#[derive(Component, Deref)]
+pub struct ChunkWater(pub Entity);
+
+/// Corners of a surface nets cell, the cell at `c` spans the centers of voxels `c..=c + 1`
+const CORNERS: [UVec3; 8] = [
+    uvec3(0, 0, 0),
+    uvec3(1, 0, 0),
+    uvec3(0, 1, 0),
+    uvec3(1, 1, 0),
+    uvec3(0, 0, 1),
+    uvec3(1, 0, 1),
+    uvec3(0, 1, 1),
+    uvec3(1, 1, 1),
+];
+
+/// Corners at either end of each cell edge
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// Water voxels of row `i_2d`, lava is liquid but stays on the greedy path
+#[inline]
+pub fn water_row<S: ChunkSize>(chunk: &Chunk<S>, i_2d: usize) -> S::Row {
+    chunk.masks.liquid_mask()[i_2d] & chunk.masks.transparent_mask[i_2d]
+}
+
+/// Surface nets vertex of `cell`, averaging where its edges cross from occupied to empty.
+///
+/// Every voxel counts as occupied so the surface meets the walls it rests against flush
+fn cell_vertex<S: ChunkSize>(chunk: &Chunk<S>, cell: UVec3) -> Vec3 {
+    let occupied = CORNERS.map(|c| chunk.masks.is_some(cell + c));
+
+    let mut sum = Vec3::ZERO;
+    let mut count = 0;
+    for (a, b) in EDGES {
+        if occupied[a] != occupied[b] {
+            sum += (CORNERS[a] + CORNERS[b]).as_vec3() / 2.;
+            count += 1;
+        }
+    }
+
+    let offset = if count == 0 {
+        Vec3::splat(0.5)
+    } else {
+        sum / count as f32
+    };
+    cell.as_vec3() + 0.5 + offset
+}
+
+/// Average horizontal flow of the liquid corners of `cell`
+fn cell_flow<S: ChunkSize>(chunk: &Chunk<S>, cell: UVec3) -> Vec2 {
+    let mut sum = Vec3::ZERO;
+    let mut count = 0;
+    for c in CORNERS {
+        if chunk.masks.is_liquid(cell + c) {
+            sum += chunk.flow.get(cell + c);
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        Vec2::ZERO
+    } else {
+        sum.xz() / count as f32
+    }
+}
+
+/// Smooth surface between water and empty voxels in world space.
+///
+/// Water against solids or ice has no surface since those faces are hidden anyway
+pub fn water_mesh<S: ChunkSize>(chunk: &Chunk<S>, origin: IVec3) -> Mesh {
+    let mut vertices = HashMap::<UVec3, u32>::default();
+    let mut positions = Vec::<Vec3>::new();
+    let mut flows = Vec::<Vec2>::new();
+    let mut indices = Vec::<u32>::new();
+
+    for z in 1..S::LEN_U32 - 1 {
+        for y in 1..S::LEN_U32 - 1 {
+            let i_2d = [y, z].i_2d::<S>();
+
+            for x in (water_row(chunk, i_2d) & !S::Row::PAD)
+                .ones()
+                .map(|x| x as u32)
+            {
+                let p = uvec3(x, y, z);
+
+                for axis in 0..3 {
+                    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+                    for positive in [true, false] {
+                        let mut n = p;
+                        n[axis] = if positive { n[axis] + 1 } else { n[axis] - 1 };
+                        if chunk.masks.is_some(n) {
+                            continue;
+                        }
+
+                        // the 4 cells around the edge between p and n
+                        let cells = [(0, 0), (1, 0), (1, 1), (0, 1)].map(|(du, dv)| {
+                            let mut cell = p;
+                            cell[axis] = p[axis].min(n[axis]);
+                            cell[u] = p[u] - 1 + du;
+                            cell[v] = p[v] - 1 + dv;
+                            *vertices.entry(cell).or_insert_with(|| {
+                                positions.push(cell_vertex(chunk, cell) + origin.as_vec3());
+                                flows.push(cell_flow(chunk, cell));
+                                positions.len() as u32 - 1
+                            })
+                        });
+
+                        let mut quad = [0, 1, 2, 3];
+                        let [a, b, c, _] = cells.map(|i| positions[i as usize]);
+                        let mut dir = Vec3::ZERO;
+                        dir[axis] = if positive { 1. } else { -1. };
+                        if (b - a).cross(c - a).dot(dir) < 0. {
+                            quad.reverse();
+                        }
+
+                        for i in [0, 1, 2, 0, 2, 3] {
+                            indices.push(cells[quad[i]]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(ATTRIBUTE_FLOW, flows)
+    .with_inserted_indices(Indices::U32(indices))
+    .with_computed_smooth_normals()
+}
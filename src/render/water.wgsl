@@ -0,0 +1,146 @@
+#import bevy_pbr::{
+    mesh_functions::{get_world_from_local, mesh_position_local_to_world, mesh_normal_local_to_world},
+    mesh_view_bindings::{view, globals, lights},
+    mesh_view_types::DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT,
+    shadows::fetch_directional_shadow,
+    view_transformations::position_world_to_clip,
+}
+
+struct LiquidSettings {
+    color: vec4<f32>,
+    // height of the waves on upward facing surfaces
+    wave_amplitude: f32,
+    wave_speed: f32,
+    // normal map repeats per voxel
+    ripple_scale: f32,
+    // how far the ripples bend the normal
+    ripple_strength: f32,
+    // normal map texels scrolled per voxel per tick of flow every second
+    flow_speed: f32,
+    sky_brightness: f32,
+}
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(0) var<uniform> settings: LiquidSettings;
+@group(#{MATERIAL_BIND_GROUP}) @binding(1) var skybox: texture_cube<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(2) var skybox_sampler: sampler;
+@group(#{MATERIAL_BIND_GROUP}) @binding(3) var normal_map: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(4) var normal_map_sampler: sampler;
+
+// reflectance looking straight down at water
+const F0: f32 = 0.02;
+// brightness of the water's own colour where the sun is blocked
+const SHADOW_BRIGHTNESS: f32 = 0.5;
+// seconds before a flow layer jumps back to where it started
+const FLOW_PERIOD: f32 = 2.0;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) flow: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) flow: vec2<f32>,
+}
+
+// a few travelling sines, only a function of position so shared vertices stay shared
+fn wave(p: vec2<f32>, t: f32) -> f32 {
+    return sin(p.x * 0.7 + t) * 0.5
+        + sin(p.y * 0.9 + t * 1.3) * 0.3
+        + sin((p.x + p.y) * 1.7 + t * 0.7) * 0.2;
+}
+
+// how far each of two flow layers half a period apart has scrolled, in periods, and the weight of
+// the second. Each layer fades out as it jumps back so scrolling never runs away with time
+fn flow_phases() -> vec3<f32> {
+    let a = fract(globals.time / FLOW_PERIOD);
+    let b = fract(globals.time / FLOW_PERIOD + 0.5);
+    return vec3(a, b, abs(a - 0.5) * 2.0);
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    let world_from_local = get_world_from_local(vertex.instance_index);
+    var world_position = mesh_position_local_to_world(world_from_local, vec4(vertex.position, 1.0));
+    let world_normal = mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
+
+    // only the top of the water moves, scaled down where the surface turns to a wall
+    let up = clamp(world_normal.y, 0.0, 1.0);
+    let t = globals.time * settings.wave_speed;
+    let phases = flow_phases();
+    let drift = vertex.flow * FLOW_PERIOD * settings.wave_speed;
+    let height = mix(
+        wave(world_position.xz - drift * phases.x, t),
+        wave(world_position.xz - drift * phases.y, t),
+        phases.z,
+    );
+    world_position.y += height * settings.wave_amplitude * up;
+
+    out.world_position = world_position;
+    out.world_normal = world_normal;
+    out.flow = vertex.flow;
+    out.position = position_world_to_clip(world_position.xyz);
+
+    return out;
+}
+
+fn ripple(uv: vec2<f32>) -> vec2<f32> {
+    return textureSample(normal_map, normal_map_sampler, uv).xy * 2.0 - 1.0;
+}
+
+// two layers scrolling across each other so the pattern never visibly repeats
+fn ripples(uv: vec2<f32>, scroll: vec2<f32>, t: f32) -> vec2<f32> {
+    return ripple(uv - scroll + vec2(t * 0.02, t * 0.01))
+        + ripple(uv * 1.7 - scroll * 1.3 + vec2(-t * 0.015, t * 0.025));
+}
+
+// 1 where the sun reaches `world_position`, 0 where every directional light is blocked
+fn sunlight(world_position: vec4<f32>, normal: vec3<f32>) -> f32 {
+    let view_z = dot(vec4(
+        view.view_from_world[0].z,
+        view.view_from_world[1].z,
+        view.view_from_world[2].z,
+        view.view_from_world[3].z
+    ), world_position);
+
+    var light = 1.0;
+    for (var i = 0u; i < lights.n_directional_lights; i += 1u) {
+        if (lights.directional_lights[i].flags & DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u {
+            light = min(light, fetch_directional_shadow(i, world_position, normal, view_z));
+        }
+    }
+    return light;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = globals.time;
+    let uv = in.world_position.xz * settings.ripple_scale;
+    let phases = flow_phases();
+    let scroll = in.flow * settings.flow_speed * FLOW_PERIOD;
+
+    let bend = mix(
+        ripples(uv, scroll * phases.x, t),
+        ripples(uv, scroll * phases.y, t),
+        phases.z,
+    );
+
+    let n = normalize(normalize(in.world_normal) + vec3(bend.x, 0.0, bend.y) * settings.ripple_strength);
+    let v = normalize(view.world_position.xyz - in.world_position.xyz);
+
+    let fresnel = F0 + (1.0 - F0) * pow(1.0 - max(dot(n, v), 0.0), 5.0);
+    let sky = textureSample(skybox, skybox_sampler, reflect(-v, n)).rgb * settings.sky_brightness;
+
+    // shadows darken what's seen through the surface, not what it reflects
+    let shade = mix(SHADOW_BRIGHTNESS, 1.0, sunlight(in.world_position, normalize(in.world_normal)));
+    let color = mix(settings.color.rgb * shade, sky, fresnel);
+    let alpha = mix(settings.color.a, 1.0, fresnel);
+
+    return vec4(color, alpha);
+}
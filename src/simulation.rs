@@ -0,0 +1,661 @@
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::platform::time::Instant;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::chunk::flow::FlowDelta;
+use crate::chunk::index::Index3d;
+use crate::chunk::map::{ChunkMap, ChunkPos};
+use crate::chunk::temperature::{self, AMBIENT};
+use crate::chunk::{BoxChunk, Chunk, ChunkSize, DefaultSize, Voxel};
+use crate::render::ChunkMeshChanges;
+
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationSettings>()
+            .init_resource::<SimulationStats>()
+            .init_resource::<SimulationMode>()
+            .init_resource::<History>()
+            .add_message::<Rewind>();
+
+        app.add_systems(FixedUpdate, schedule_ticks)
+            .add_systems(Update, (rewind, run_ticks, relight).chain());
+    }
+}
+
+#[derive(Resource)]
+pub struct SimulationSettings {
+    /// Liquid ticks per fixed tick
+    pub substeps: u32,
+    /// CPU time per frame spent ticking before the rest is deferred to later frames
+    pub budget: Duration,
+    /// Ticks scheduled past this are dropped instead of queued
+    pub max_backlog: u32,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            substeps: 1,
+            budget: Duration::from_millis(8),
+            max_backlog: 256,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SimulationStats {
+    pub tick: u64,
+    /// Ticks scheduled but not yet run
+    pub backlog: u32,
+    pub dropped: u64,
+    pub ticks_last_frame: u32,
+    pub time_last_frame: Duration,
+}
+
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulationMode {
+    #[default]
+    Running,
+    Paused,
+    /// Runs this many more ticks then pauses
+    Stepping(u32),
+}
+
+/// Chunks close enough to the camera for [`run_ticks`] to tick them, the rest stay still
+#[derive(Component)]
+pub struct Simulated;
+
+/// Undoes this many ticks from [`History`] and pauses
+#[derive(Message)]
+pub struct Rewind(pub u32);
+
+/// What a tick changed in one chunk, undone in reverse
+#[derive(Default)]
+struct ChunkDelta {
+    /// `(i_3d, previous voxel, previous temperature)` in the order they were changed
+    voxels: Vec<(usize, Option<Voxel>, Option<f32>)>,
+    /// `None` for chunks only written into by their neighbours
+    flow: Option<FlowDelta>,
+}
+
+impl ChunkDelta {
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.voxels.is_empty() && self.flow.as_ref().is_none_or(FlowDelta::is_empty)
+    }
+}
+
+/// Ring buffer of per-tick deltas, oldest first.
+///
+/// Each delta lists a [`ChunkDelta`] per chunk that changed, undone in reverse. Temperature is
+/// only restored where voxels changed, heat diffused elsewhere stays, and edits made outside the
+/// tick aren't undone. Chunks no longer loaded are skipped
+#[derive(Resource)]
+pub struct History {
+    deltas: VecDeque<Vec<(IVec3, ChunkDelta)>>,
+    pub capacity: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            deltas: VecDeque::new(),
+            capacity: 600,
+        }
+    }
+}
+
+impl History {
+    fn push(&mut self, delta: Vec<(IVec3, ChunkDelta)>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+    }
+}
+
+fn schedule_ticks(
+    settings: Res<SimulationSettings>,
+    mode: Res<SimulationMode>,
+    mut stats: ResMut<SimulationStats>,
+) {
+    if *mode != SimulationMode::Running {
+        return;
+    }
+
+    let scheduled = stats.backlog + settings.substeps;
+
+    stats.backlog = scheduled.min(settings.max_backlog);
+    stats.dropped += (scheduled - stats.backlog) as u64;
+}
+
+fn rewind(
+    mut chunks: Query<(&mut BoxChunk, &mut ChunkMeshChanges)>,
+    map: Res<ChunkMap>,
+    mut history: ResMut<History>,
+    mut mode: ResMut<SimulationMode>,
+    mut stats: ResMut<SimulationStats>,
+    mut rewinds: MessageReader<Rewind>,
+) {
+    for Rewind(n) in rewinds.read() {
+        *mode = SimulationMode::Paused;
+
+        for _ in 0..*n {
+            let Some(delta) = history.deltas.pop_back() else {
+                break;
+            };
+
+            for (pos, chunk_delta) in delta.into_iter().rev() {
+                let Some((mut chunk, mut changes)) =
+                    map.get(&pos).and_then(|&e| chunks.get_mut(e).ok())
+                else {
+                    continue;
+                };
+
+                chunk.unsaved = true;
+                for (i, v, t) in chunk_delta.voxels.into_iter().rev() {
+                    chunk.voxels.set(i, v);
+                    chunk.masks.set(i, v);
+                    if let (Some(temperature), Some(t)) = (&mut chunk.temperature, t) {
+                        temperature.set(i, t);
+                    }
+                    chunk.invalidate_light(i);
+                    changes.push::<DefaultSize>(i);
+                }
+                if let Some(flow) = chunk_delta.flow {
+                    chunk.flow.undo(flow);
+                }
+            }
+
+            stats.tick -= 1;
+        }
+    }
+}
+
+pub fn run_ticks(
+    mut chunks: Query<(&mut BoxChunk, &mut ChunkMeshChanges)>,
+    simulated: Query<(Entity, &ChunkPos), With<Simulated>>,
+    map: Res<ChunkMap>,
+    settings: Res<SimulationSettings>,
+    mut mode: ResMut<SimulationMode>,
+    mut stats: ResMut<SimulationStats>,
+    mut history: ResMut<History>,
+) {
+    let start = Instant::now();
+    let mut ran = 0;
+
+    loop {
+        let pending = match *mode {
+            SimulationMode::Running => stats.backlog,
+            SimulationMode::Paused => 0,
+            SimulationMode::Stepping(n) => n,
+        };
+        if pending == 0 {
+            break;
+        }
+
+        let mut delta = Vec::new();
+        let mut border = Vec::new();
+        let mut border_moves = Vec::new();
+        for (entity, pos) in &simulated {
+            let (mut chunk, mut changes) = chunks.get_mut(entity).unwrap();
+            let chunk_delta = tick(&mut chunk, &mut changes, stats.tick);
+
+            let world = |i: usize| pos.origin() + UVec3::from(i.xyz::<DefaultSize>()).as_ivec3();
+            border.extend(
+                chunk_delta
+                    .voxels
+                    .iter()
+                    .filter(|&&(i, ..)| on_border(i))
+                    .map(|&(i, ..)| world(i)),
+            );
+            border_moves.extend(
+                chunk
+                    .border_moves
+                    .drain(..)
+                    .map(|(src, dst)| (world(src), world(dst))),
+            );
+
+            if !chunk_delta.is_empty() {
+                delta.push((**pos, chunk_delta));
+            }
+        }
+
+        // writes reaching into other chunks, recorded apart since they may land in any of them
+        let mut shared = HashMap::<IVec3, ChunkDelta>::default();
+
+        // neighbours mesh and tick against copies of the border in their padding
+        for p in border {
+            if let Some(v) = voxel_at(&chunks, &map, p) {
+                write_voxel(&mut chunks, &map, &mut shared, p, v);
+            }
+        }
+
+        // dropped if the neighbour isn't loaded or something else got there first
+        for (src, dst) in border_moves {
+            if let (Some(v @ Some(_)), Some(None)) =
+                (voxel_at(&chunks, &map, src), voxel_at(&chunks, &map, dst))
+            {
+                write_voxel(&mut chunks, &map, &mut shared, dst, v);
+                write_voxel(&mut chunks, &map, &mut shared, src, None);
+            }
+        }
+
+        delta.extend(shared);
+        history.push(delta);
+
+        share_temperatures(&mut chunks, &simulated, &map);
+
+        stats.tick += 1;
+        ran += 1;
+
+        match &mut *mode {
+            SimulationMode::Stepping(1) => *mode = SimulationMode::Paused,
+            SimulationMode::Stepping(n) => *n -= 1,
+            _ => stats.backlog -= 1,
+        }
+
+        if start.elapsed() >= settings.budget {
+            break;
+        }
+    }
+
+    stats.ticks_last_frame = ran;
+    stats.time_last_frame = start.elapsed();
+}
+
+/// Sets world voxel `p` in every loaded chunk holding it, padding included so neighbours mesh
+/// against it. Chunks get temperature the first time something that melts or freezes is placed
+pub fn set_voxel(
+    chunks: &mut Query<(&mut BoxChunk, &mut ChunkMeshChanges)>,
+    map: &ChunkMap,
+    p: IVec3,
+    v: Option<Voxel>,
+) {
+    for (pos, local) in ChunkPos::overlapping(p) {
+        let Some((mut chunk, mut changes)) = map.get(&*pos).and_then(|&e| chunks.get_mut(e).ok())
+        else {
+            continue;
+        };
+
+        if chunk.temperature.is_none() && v.and_then(temperature::initial).is_some() {
+            chunk.enable_temperature(AMBIENT);
+        }
+        chunk.set(local, v);
+        changes.push::<DefaultSize>(local);
+    }
+}
+
+/// Copies the border temperatures of every simulated chunk's neighbours into its padding so heat
+/// crosses between chunks, like [`run_ticks`] does with voxels. Chunks without temperature get it
+/// once a neighbour's border is noticeably off ambient
+fn share_temperatures(
+    chunks: &mut Query<(&mut BoxChunk, &mut ChunkMeshChanges)>,
+    simulated: &Query<(Entity, &ChunkPos), With<Simulated>>,
+    map: &ChunkMap,
+) {
+    for (entity, pos) in simulated {
+        for normal in [
+            IVec3::X,
+            IVec3::NEG_X,
+            IVec3::Y,
+            IVec3::NEG_Y,
+            IVec3::Z,
+            IVec3::NEG_Z,
+        ] {
+            let Some((neighbour, _)) = map.get(&(**pos + normal)).and_then(|&e| chunks.get(e).ok())
+            else {
+                continue;
+            };
+            let border: Option<Vec<f32>> = neighbour
+                .temperature
+                .as_ref()
+                .map(|temperature| temperature.border(-normal).collect());
+
+            let (mut chunk, _) = chunks.get_mut(entity).unwrap();
+            if chunk.temperature.is_none() {
+                let noticeable = border.as_ref().is_some_and(|border| {
+                    border
+                        .iter()
+                        .any(|t| (t - AMBIENT).abs() > temperature::NOTICEABLE)
+                });
+                if !noticeable {
+                    continue;
+                }
+                chunk.enable_temperature(AMBIENT);
+            }
+
+            let temperature = chunk.temperature.as_mut().unwrap();
+            match border {
+                Some(border) => temperature.set_padding(normal, border),
+                None => temperature.set_padding(normal, std::iter::repeat(AMBIENT)),
+            }
+        }
+    }
+}
+
+/// Whether `i` is a voxel next to the padding, which neighbours keep a copy of
+#[inline]
+fn on_border(i: usize) -> bool {
+    let p = UVec3::from(i.xyz::<DefaultSize>());
+    p.cmpeq(UVec3::ONE).any() || p.cmpeq(UVec3::splat(DefaultSize::LEN_U32 - 2)).any()
+}
+
+/// World voxel `p` from the chunk containing it, `None` if that isn't loaded
+fn voxel_at(
+    chunks: &Query<(&mut BoxChunk, &mut ChunkMeshChanges)>,
+    map: &ChunkMap,
+    p: IVec3,
+) -> Option<Option<Voxel>> {
+    let (pos, local) = ChunkPos::containing(p);
+    let (chunk, _) = chunks.get(*map.get(&*pos)?).ok()?;
+    Some(chunk.voxels[local.i_3d::<DefaultSize>()])
+}
+
+/// [`set_voxel`] recording what every chunk held before in `delta`
+fn write_voxel(
+    chunks: &mut Query<(&mut BoxChunk, &mut ChunkMeshChanges)>,
+    map: &ChunkMap,
+    delta: &mut HashMap<IVec3, ChunkDelta>,
+    p: IVec3,
+    v: Option<Voxel>,
+) {
+    for (pos, local) in ChunkPos::overlapping(p) {
+        if let Some((chunk, _)) = map.get(&*pos).and_then(|&e| chunks.get(e).ok()) {
+            let i = local.i_3d::<DefaultSize>();
+            let t = chunk
+                .temperature
+                .as_ref()
+                .map(|temperature| temperature.get(i));
+            delta
+                .entry(*pos)
+                .or_default()
+                .voxels
+                .push((i, chunk.voxels[i], t));
+        }
+    }
+    set_voxel(chunks, map, p, v);
+}
+
+/// Relights voxels changed since last frame and remeshes wherever the light changed. Changes
+/// near a side are copied from the chunk holding them into the padding of every other chunk
+/// that overlaps them, so borders reach the neighbours and padding lit again as a guess gets
+/// the real levels back
+pub fn relight(
+    mut chunks: Query<(&ChunkPos, &mut BoxChunk, &mut ChunkMeshChanges)>,
+    map: Res<ChunkMap>,
+) {
+    let last = DefaultSize::LEN_U32 - 1;
+    let near_side = |c: u32| c <= 1 || c >= last - 1;
+    let mut shared = HashSet::<IVec3>::default();
+
+    for (pos, mut chunk, mut changes) in &mut chunks {
+        chunk.update_light();
+
+        if let Some(light) = &mut chunk.light {
+            for i in light.changes.drain(..) {
+                changes.push_light::<DefaultSize>(i);
+
+                let local = i.xyz::<DefaultSize>();
+                if local.into_iter().any(near_side) {
+                    shared.insert(pos.origin() + UVec3::from(local).as_ivec3());
+                }
+            }
+        }
+    }
+
+    for p in shared {
+        let (owner, local) = ChunkPos::containing(p);
+        let Some(packed) = map
+            .get(&*owner)
+            .and_then(|&e| chunks.get(e).ok())
+            .and_then(|(_, chunk, _)| chunk.light.as_ref())
+            .map(|light| light.packed(local))
+        else {
+            continue;
+        };
+
+        for (pos, local) in ChunkPos::overlapping(p).skip(1) {
+            let Some(&entity) = map.get(&*pos) else {
+                continue;
+            };
+            let (_, mut chunk, mut changes) = chunks.get_mut(entity).unwrap();
+            if let Some(light) = &mut chunk.light
+                && light.packed(local) != packed
+            {
+                light.set_padding(local, packed);
+                changes.push_light::<DefaultSize>(local);
+            }
+        }
+    }
+}
+
+/// Returns the delta needed to undo the tick
+fn tick(chunk: &mut Chunk, changes: &mut ChunkMeshChanges, tick: u64) -> ChunkDelta {
+    chunk.liquid_tick(tick);
+    chunk.masks.dblt_masks.copy_back_to_front();
+
+    let mut flow = chunk.flow.decay();
+
+    let mut voxels = Vec::with_capacity(chunk.dst_to_src.len() * 2);
+    // every move swaps a voxel with an empty one, temperature and all
+    let mut swapped = HashMap::<usize, usize>::default();
+
+    for (dst, src) in chunk.dst_to_src.drain() {
+        chunk.flow.record(dst, src, &mut flow);
+
+        // dst was empty and src held what's now at dst
+        voxels.push((dst, None));
+        voxels.push((src, chunk.voxels[dst]));
+        swapped.insert(dst, src);
+        swapped.insert(src, dst);
+
+        changes.push::<DefaultSize>(dst);
+        changes.push::<DefaultSize>(src);
+    }
+
+    chunk.temperature_tick();
+
+    if let Some(temperature) = &mut chunk.temperature {
+        for (i, prev) in temperature.phase_changes.drain(..) {
+            voxels.push((i, Some(prev)));
+            changes.push::<DefaultSize>(i);
+        }
+    }
+
+    if let Some(light) = &mut chunk.light {
+        for &(i, _) in &voxels {
+            light.invalidate(i);
+        }
+    }
+
+    chunk.unsaved |= !voxels.is_empty();
+
+    let temperature = chunk.temperature.as_ref();
+    ChunkDelta {
+        voxels: voxels
+            .into_iter()
+            .map(|(i, v)| {
+                let before = swapped.get(&i).copied().unwrap_or(i);
+                (i, v, temperature.map(|t| t.before_diffusion(before)))
+            })
+            .collect(),
+        flow: Some(flow),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn liquid_falls_into_the_chunk_below() {
+        let mut app = App::new();
+        app.init_resource::<ChunkMap>()
+            .init_resource::<SimulationSettings>()
+            .init_resource::<SimulationMode>()
+            .init_resource::<History>()
+            .insert_resource(SimulationStats {
+                backlog: 1,
+                ..default()
+            })
+            .add_systems(Update, run_ticks);
+
+        let mut upper = Chunk::<DefaultSize>::default();
+        upper.set([5, 1, 5], Some(Voxel::Liquid));
+        for (pos, chunk) in [(IVec3::ZERO, Chunk::default()), (IVec3::Y, upper)] {
+            app.world_mut().spawn((
+                BoxChunk::from(chunk),
+                ChunkPos(pos),
+                ChunkMeshChanges::default(),
+                Simulated,
+            ));
+        }
+
+        app.update();
+
+        let voxel = |pos: IVec3, p: [u32; 3]| {
+            let entity = app.world().resource::<ChunkMap>()[&pos];
+            app.world().get::<BoxChunk>(entity).unwrap().voxels[p.i_3d::<DefaultSize>()]
+        };
+        let top = DefaultSize::LEN_U32 - 2;
+
+        assert_eq!(voxel(IVec3::Y, [5, 1, 5]), None);
+        assert_eq!(voxel(IVec3::Y, [5, 0, 5]), Some(Voxel::Liquid));
+        assert_eq!(voxel(IVec3::ZERO, [5, top, 5]), Some(Voxel::Liquid));
+        assert_eq!(voxel(IVec3::ZERO, [5, top + 1, 5]), None);
+    }
+
+    #[test]
+    fn rewinding_a_tick_restores_the_chunk() {
+        let mut app = App::new();
+        app.init_resource::<ChunkMap>()
+            .init_resource::<SimulationSettings>()
+            .init_resource::<SimulationMode>()
+            .init_resource::<History>()
+            .init_resource::<SimulationStats>()
+            .add_message::<Rewind>()
+            .add_systems(Update, (rewind, run_ticks).chain());
+
+        let mut chunk = Chunk::<DefaultSize>::default();
+        chunk.enable_temperature(AMBIENT);
+        chunk.set([30, 30, 30], Some(Voxel::Liquid));
+        let entity = app
+            .world_mut()
+            .spawn((
+                BoxChunk::from(chunk),
+                ChunkPos(IVec3::ZERO),
+                ChunkMeshChanges::default(),
+                Simulated,
+            ))
+            .id();
+
+        let tick = |app: &mut App| {
+            app.world_mut().resource_mut::<SimulationStats>().backlog = 1;
+            app.update();
+        };
+
+        // a first tick so the falling liquid has some flow
+        tick(&mut app);
+
+        // liquid just above freezing walled in by ice, freezing on the next tick
+        let freezing = [10, 10, 10];
+        {
+            let mut chunk = app.world_mut().get_mut::<BoxChunk>(entity).unwrap();
+            for d in [IVec3::X, IVec3::Y, IVec3::Z] {
+                for d in [d, -d] {
+                    let p = (UVec3::from(freezing).as_ivec3() + d).as_uvec3();
+                    chunk.set(p, Some(Voxel::Ice));
+                }
+            }
+            chunk.set(freezing, Some(Voxel::Liquid));
+            chunk.temperature.as_mut().unwrap().set(freezing, -0.5);
+        }
+
+        let before = app.world_mut().get_mut::<BoxChunk>(entity).unwrap();
+        let voxels: Vec<_> = before.voxels.iter().copied().collect();
+        let some: Vec<_> = before.masks.some_mask().iter().copied().collect();
+        let liquid: Vec<_> = before.masks.liquid_mask().iter().copied().collect();
+        let transparent: Vec<_> = before.masks.transparent_mask.iter().copied().collect();
+        let temperatures = [[30, 29, 30], [30, 28, 30], freezing]
+            .map(|p| before.temperature.as_ref().unwrap().get(p));
+        let flow = before.flow.clone();
+
+        tick(&mut app);
+        assert_eq!(
+            app.world_mut().get_mut::<BoxChunk>(entity).unwrap().voxels
+                [freezing.i_3d::<DefaultSize>()],
+            Some(Voxel::Ice)
+        );
+
+        app.world_mut().write_message(Rewind(1));
+        app.update();
+
+        let after = app.world_mut().get_mut::<BoxChunk>(entity).unwrap();
+        assert!(after.voxels.iter().copied().eq(voxels));
+        assert!(after.masks.some_mask().iter().copied().eq(some));
+        assert!(after.masks.liquid_mask().iter().copied().eq(liquid));
+        assert!(after.masks.transparent_mask.iter().copied().eq(transparent));
+        assert_eq!(
+            [[30, 29, 30], [30, 28, 30], freezing].map(|p| after
+                .temperature
+                .as_ref()
+                .unwrap()
+                .get(p)),
+            temperatures
+        );
+
+        assert_eq!(after.flow.len(), flow.len());
+        for (i, v) in flow.iter() {
+            assert!(after.flow.get(*i).abs_diff_eq(*v, 1e-5), "flow at {i}");
+        }
+    }
+
+    #[test]
+    fn heat_crosses_into_the_neighbour() {
+        let mut app = App::new();
+        app.init_resource::<ChunkMap>()
+            .init_resource::<SimulationSettings>()
+            .init_resource::<SimulationMode>()
+            .init_resource::<History>()
+            .insert_resource(SimulationStats {
+                backlog: 1,
+                ..default()
+            })
+            .add_systems(Update, run_ticks);
+
+        // lava resting on the floor of the chunk, right against the +X border
+        let top = DefaultSize::LEN_U32 - 2;
+        let mut hot = Chunk::<DefaultSize>::from_fn(|[_, y, _]| (y == 0).then_some(Voxel::Solid));
+        hot.set([top, 1, 5], Some(Voxel::Lava));
+        hot.enable_temperature(AMBIENT);
+
+        let mut entities = Vec::new();
+        for (pos, chunk) in [(IVec3::ZERO, hot), (IVec3::X, Chunk::default())] {
+            let entity = app.world_mut().spawn((
+                BoxChunk::from(chunk),
+                ChunkPos(pos),
+                ChunkMeshChanges::default(),
+                Simulated,
+            ));
+            entities.push(entity.id());
+        }
+
+        app.update();
+
+        let temperature = |entity: Entity, p: [u32; 3]| {
+            let chunk = app.world().get::<BoxChunk>(entity).unwrap();
+            chunk.temperature.as_ref().map(|t| t.get(p))
+        };
+        let padding = temperature(entities[1], [0, 1, 5]).expect("neighbour has temperature");
+        assert_eq!(Some(padding), temperature(entities[0], [top, 1, 5]));
+        assert!(padding > temperature::SOLIDIFY);
+    }
+}
@@ -0,0 +1,390 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::tasks::futures::check_ready;
+use bevy::tasks::{AsyncComputeTaskPool, IoTaskPool, Task, block_on};
+
+use crate::chunk::map::{ChunkMap, ChunkPos, INNER};
+use crate::chunk::temperature::{self, AMBIENT};
+use crate::chunk::{BoxChunk, Chunk, DefaultSize};
+use crate::flycam::FlyCam;
+use crate::render::ChunkMeshChanges;
+use crate::render::water::ChunkWater;
+use crate::simulation::Simulated;
+use crate::worldgen::{self, WorldGen, WorldSeed};
+use crate::{ChunkAssets, spawn_chunk};
+
+pub struct StreamingPlugin;
+
+impl Plugin for StreamingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StreamingSettings>()
+            .init_resource::<Loading>()
+            .init_resource::<Saving>()
+            .add_systems(
+                Update,
+                (
+                    finish_saves,
+                    spawn_loaded_chunks,
+                    sync_padding,
+                    unload_chunks,
+                    load_chunks,
+                    update_simulated,
+                )
+                    .chain()
+                    .after(worldgen::spawn_generated_chunks),
+            )
+            .add_systems(Last, save_on_exit);
+    }
+}
+
+/// Which chunks are kept around the camera's, each as the half extents in chunks of a box
+/// centered on it
+#[derive(Resource)]
+pub struct StreamingSettings {
+    /// Missing chunks in here are loaded, or generated if they were never saved, nearest first
+    pub load: IVec3,
+    /// Chunks outside of this are saved if they changed and unloaded. Past `load` so chunks at
+    /// the edge don't come and go as the camera moves back and forth
+    pub unload: IVec3,
+    /// Chunks in here are [`Simulated`], within `load` so only liquid near the camera moves
+    pub simulation: IVec3,
+    /// Most chunks loaded or generated at once
+    pub max_loading: usize,
+    /// Chunks are saved under here, in a directory per [`WorldSeed`]
+    pub save_dir: PathBuf,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        Self {
+            load: ivec3(4, 2, 4),
+            unload: ivec3(5, 3, 5),
+            simulation: IVec3::ONE,
+            max_loading: 8,
+            save_dir: PathBuf::from("world"),
+        }
+    }
+}
+
+/// Chunks being read from their saves, `None` once it turns out there's none
+#[derive(Resource, Default, Deref, DerefMut)]
+struct Loading(HashMap<IVec3, Task<Option<BoxChunk>>>);
+
+/// Chunks being written out, not loaded again until they're done
+#[derive(Resource, Default, Deref, DerefMut)]
+struct Saving(HashMap<IVec3, Task<()>>);
+
+/// Whether chunk `pos` is in the box of half extents `extents` around chunk `center`
+#[inline]
+fn within(pos: IVec3, center: IVec3, extents: IVec3) -> bool {
+    (pos - center).abs().cmple(extents).all()
+}
+
+fn chunk_path(dir: &Path, seed: u32, pos: ChunkPos) -> PathBuf {
+    dir.join(seed.to_string())
+        .join(format!("{}_{}_{}.chunk", pos.x, pos.y, pos.z))
+}
+
+fn read(path: &Path) -> io::Result<Chunk> {
+    Chunk::read(&mut BufReader::new(File::open(path)?))
+}
+
+fn write(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    // renamed over the old one so it's never read half written
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(tmp, path)
+}
+
+/// Writes out `bytes` of chunk `pos`, warning if that fails
+fn save(path: &Path, bytes: &[u8], pos: ChunkPos) {
+    if let Err(e) = write(path, bytes) {
+        warn!("couldn't save {pos:?} to {}: {e}", path.display());
+    }
+}
+
+/// Chunk `pos` as it was saved, `None` if it never was or the save can't be read
+fn load(path: &Path, seed: u32, pos: ChunkPos) -> Option<BoxChunk> {
+    let mut chunk: BoxChunk = match read(path) {
+        Ok(chunk) => chunk.into(),
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!(
+                    "generating {pos:?} again, couldn't load {}: {e}",
+                    path.display()
+                );
+            }
+            return None;
+        }
+    };
+
+    worldgen::light_loaded(seed, pos, &mut chunk);
+    if chunk
+        .voxels
+        .iter()
+        .any(|v| v.and_then(temperature::initial).is_some())
+    {
+        chunk.enable_temperature(AMBIENT);
+    }
+    Some(chunk)
+}
+
+/// Forgets the saves that are done, letting their chunks load again
+fn finish_saves(mut saving: ResMut<Saving>) {
+    saving.retain(|_, task| !task.is_finished());
+}
+
+/// Spawns every chunk that finished loading, asking for the ones never saved to be generated
+fn spawn_loaded_chunks(
+    mut commands: Commands,
+    mut loading: ResMut<Loading>,
+    mut worldgen: WorldGen,
+    assets: Res<ChunkAssets>,
+) {
+    loading.retain(|&pos, task| {
+        let Some(chunk) = check_ready(task) else {
+            return true;
+        };
+        match chunk {
+            Some(chunk) => spawn_chunk(&mut commands, &assets, ChunkPos(pos), chunk),
+            None => worldgen.request(ChunkPos(pos)),
+        }
+        false
+    });
+}
+
+/// Copies between the chunks spawned since last frame and each loaded neighbour whatever the
+/// other holds in its padding, remeshing the padding that changed on both sides
+pub fn sync_padding(
+    spawned: Query<(Entity, &ChunkPos), Added<ChunkPos>>,
+    mut chunks: Query<(&mut BoxChunk, &mut ChunkMeshChanges)>,
+    map: Res<ChunkMap>,
+) {
+    for (entity, pos) in &spawned {
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let offset = ivec3(x, y, z);
+                    let Some(&neighbour) = map.get(&(**pos + offset)) else {
+                        continue;
+                    };
+                    if neighbour == entity {
+                        continue;
+                    }
+
+                    let [(mut chunk, mut changes), (mut other, mut other_changes)] =
+                        chunks.get_many_mut([entity, neighbour]).unwrap();
+                    for i in chunk.copy_padding(&other, offset) {
+                        changes.push::<DefaultSize>(i);
+                    }
+                    for i in other.copy_padding(&chunk, -offset) {
+                        other_changes.push::<DefaultSize>(i);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Saves the chunks that changed and unloads every chunk that's too far from the camera,
+/// cancelling the loads and generation that are too
+#[allow(clippy::too_many_arguments)]
+fn unload_chunks(
+    mut commands: Commands,
+    camera: Single<&Transform, With<FlyCam>>,
+    chunks: Query<(Entity, &ChunkPos, &BoxChunk, &ChunkWater)>,
+    settings: Res<StreamingSettings>,
+    seed: Res<WorldSeed>,
+    mut loading: ResMut<Loading>,
+    mut saving: ResMut<Saving>,
+    mut worldgen: WorldGen,
+) {
+    let pool = IoTaskPool::get();
+    let (center, _) = ChunkPos::containing(camera.translation.floor().as_ivec3());
+
+    loading.retain(|&pos, _| within(pos, *center, settings.unload));
+    worldgen.retain(|pos| within(pos, *center, settings.unload));
+
+    for (entity, &pos, chunk, water) in &chunks {
+        if within(*pos, *center, settings.unload) {
+            continue;
+        }
+
+        if chunk.unsaved {
+            let mut bytes = Vec::new();
+            chunk.write(&mut bytes).unwrap();
+            let path = chunk_path(&settings.save_dir, **seed, pos);
+
+            let task = pool.spawn(async move { save(&path, &bytes, pos) });
+            saving.insert(*pos, task);
+        }
+
+        commands.entity(**water).despawn();
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Saves every chunk that changed when the app exits, after the saves already in flight
+fn save_on_exit(
+    mut exits: MessageReader<AppExit>,
+    chunks: Query<(&ChunkPos, &BoxChunk)>,
+    settings: Res<StreamingSettings>,
+    seed: Res<WorldSeed>,
+    mut saving: ResMut<Saving>,
+) {
+    if exits.is_empty() {
+        return;
+    }
+    exits.clear();
+
+    for (_, task) in saving.drain() {
+        block_on(task);
+    }
+
+    for (&pos, chunk) in &chunks {
+        if chunk.unsaved {
+            let mut bytes = Vec::new();
+            chunk.write(&mut bytes).unwrap();
+            save(&chunk_path(&settings.save_dir, **seed, pos), &bytes, pos);
+        }
+    }
+}
+
+/// Starts loading the missing chunks around the camera, nearest first
+fn load_chunks(
+    camera: Single<&Transform, With<FlyCam>>,
+    map: Res<ChunkMap>,
+    settings: Res<StreamingSettings>,
+    seed: Res<WorldSeed>,
+    mut loading: ResMut<Loading>,
+    saving: Res<Saving>,
+    worldgen: WorldGen,
+) {
+    let free = settings
+        .max_loading
+        .saturating_sub(loading.len() + worldgen.pending());
+    if free == 0 {
+        return;
+    }
+
+    let pool = AsyncComputeTaskPool::get();
+    let camera = camera.translation.floor().as_ivec3();
+    let (center, _) = ChunkPos::containing(camera);
+    let extents = settings.load;
+
+    let mut missing: Vec<_> = (-extents.z..=extents.z)
+        .flat_map(|z| {
+            (-extents.y..=extents.y)
+                .flat_map(move |y| (-extents.x..=extents.x).map(move |x| *center + ivec3(x, y, z)))
+        })
+        .filter(|pos| {
+            !map.contains_key(pos)
+                && !loading.contains_key(pos)
+                && !saving.contains_key(pos)
+                && !worldgen.is_generating(*pos)
+        })
+        .collect();
+    missing.sort_by_key(|&pos| {
+        let middle = ChunkPos(pos).origin() + IVec3::splat(INNER / 2 + 1);
+        (middle - camera).length_squared()
+    });
+
+    for pos in missing.into_iter().take(free) {
+        let pos = ChunkPos(pos);
+        let seed = **seed;
+        let path = chunk_path(&settings.save_dir, seed, pos);
+
+        let task = pool.spawn(async move { load(&path, seed, pos) });
+        loading.insert(*pos, task);
+    }
+}
+
+/// Marks the chunks near the camera [`Simulated`], unmarking the rest
+fn update_simulated(
+    mut commands: Commands,
+    camera: Single<&Transform, With<FlyCam>>,
+    chunks: Query<(Entity, &ChunkPos, Has<Simulated>)>,
+    settings: Res<StreamingSettings>,
+) {
+    let (center, _) = ChunkPos::containing(camera.translation.floor().as_ivec3());
+
+    for (entity, pos, simulated) in &chunks {
+        let near = within(**pos, *center, settings.simulation);
+        if near && !simulated {
+            commands.entity(entity).insert(Simulated);
+        } else if !near && simulated {
+            commands.entity(entity).remove::<Simulated>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Voxel;
+    use crate::chunk::index::Index3d;
+
+    #[test]
+    fn generated_neighbours_see_saved_edits() {
+        let seed = 0;
+        let dir = std::env::temp_dir().join(format!("voxel_water_{}", std::process::id()));
+        let (edited, generated) = (ChunkPos(IVec3::ZERO), ChunkPos(IVec3::X));
+        // the edited chunk's border towards the generated one, which never generates lava
+        let border = |y| [INNER as u32, y, 10];
+
+        let mut chunk = worldgen::generate(seed, edited);
+        for y in 1..=INNER as u32 {
+            chunk.set(border(y), Some(Voxel::Lava));
+        }
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        let path = chunk_path(&dir, seed, edited);
+        save(&path, &bytes, edited);
+        let loaded = load(&path, seed, edited).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut app = App::new();
+        app.init_resource::<ChunkMap>()
+            .add_systems(Update, sync_padding);
+        app.world_mut()
+            .spawn((loaded, edited, ChunkMeshChanges::default()));
+        app.update();
+        let entity = app
+            .world_mut()
+            .spawn((
+                worldgen::generate(seed, generated),
+                generated,
+                ChunkMeshChanges::default(),
+            ))
+            .id();
+        app.update();
+
+        let [loaded, neighbour] = app
+            .world_mut()
+            .query::<&BoxChunk>()
+            .get_many(
+                app.world(),
+                [app.world().resource::<ChunkMap>()[&*edited], entity],
+            )
+            .unwrap();
+        for y in 1..=INNER as u32 {
+            let padding = [0, y, 10];
+            assert_eq!(
+                neighbour.voxels[padding.i_3d::<DefaultSize>()],
+                Some(Voxel::Lava)
+            );
+            assert!(neighbour.masks.is_some(padding));
+            assert_eq!(
+                neighbour.light.as_ref().unwrap().packed(padding),
+                loaded.light.as_ref().unwrap().packed(border(y)),
+            );
+        }
+        assert!(!neighbour.unsaved);
+        assert!(app.world().get::<ChunkMeshChanges>(entity).unwrap().voxels);
+    }
+}
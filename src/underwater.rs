@@ -0,0 +1,116 @@
+use bevy::audio::Volume;
+use bevy::prelude::*;
+
+use crate::chunk::map::Chunks;
+
+const FOG_COLOR: Color = Color::srgb(0.05, 0.25, 0.45);
+const FOG_END: f32 = 24.0;
+/// Linear volume of every sink while underwater, relative to its own
+const MUFFLED: f32 = 0.3;
+
+pub struct UnderwaterPlugin;
+
+impl Plugin for UnderwaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (detect_underwater, (apply_underwater, muffle_audio)).chain(),
+        );
+    }
+}
+
+/// Whether this camera is inside a liquid voxel. Tints, fogs and muffles while it is
+#[derive(Component, Default, PartialEq, Eq)]
+pub struct Underwater(pub bool);
+
+fn detect_underwater(chunks: Chunks, cameras: Query<(&GlobalTransform, &mut Underwater)>) {
+    for (transform, mut underwater) in cameras {
+        let submerged = chunks.is_liquid_at(transform.translation());
+        underwater.set_if_neq(Underwater(submerged));
+    }
+}
+
+fn apply_underwater(
+    mut commands: Commands,
+    cameras: Query<(Entity, &Underwater), Changed<Underwater>>,
+) {
+    for (entity, underwater) in &cameras {
+        if underwater.0 {
+            commands.entity(entity).insert(DistanceFog {
+                color: FOG_COLOR,
+                falloff: FogFalloff::Linear {
+                    start: 0.0,
+                    end: FOG_END,
+                },
+                ..default()
+            });
+        } else {
+            commands.entity(entity).remove::<DistanceFog>();
+        }
+    }
+}
+
+/// Volume a sink had before it was muffled, given back when surfacing
+#[derive(Component)]
+struct Muffled(Volume);
+
+/// Scales every sink, new ones included, by [`MUFFLED`] while any camera is underwater
+fn muffle_audio(
+    mut commands: Commands,
+    cameras: Query<&Underwater>,
+    sinks: Query<(Entity, &mut AudioSink, Option<&Muffled>)>,
+) {
+    let submerged = cameras.iter().any(|u| u.0);
+
+    for (entity, mut sink, muffled) in sinks {
+        match (submerged, muffled) {
+            (true, None) => {
+                let volume = sink.volume();
+                sink.set_volume(Volume::Linear(volume.to_linear() * MUFFLED));
+                commands.entity(entity).insert(Muffled(volume));
+            }
+            (false, Some(&Muffled(volume))) => {
+                sink.set_volume(volume);
+                commands.entity(entity).remove::<Muffled>();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::map::{ChunkMap, ChunkPos};
+    use crate::chunk::{BoxChunk, Chunk, DefaultSize, Voxel};
+
+    fn underwater_at(app: &mut App, camera: Entity, y: f32) -> bool {
+        app.world_mut()
+            .entity_mut(camera)
+            .insert(GlobalTransform::from_xyz(8., y, 8.));
+        app.update();
+
+        let camera = app.world().entity(camera);
+        let underwater = camera.get::<Underwater>().unwrap().0;
+        assert_eq!(underwater, camera.contains::<DistanceFog>());
+        underwater
+    }
+
+    #[test]
+    fn fogs_camera_inside_liquid() {
+        let mut app = App::new();
+        app.init_resource::<ChunkMap>().add_systems(
+            Update,
+            (detect_underwater, (apply_underwater, muffle_audio)).chain(),
+        );
+
+        let chunk = Chunk::<DefaultSize>::from_fn(|[_, y, _]| (y < 16).then_some(Voxel::Liquid));
+        app.world_mut()
+            .spawn((BoxChunk::from(chunk), ChunkPos(IVec3::ZERO)));
+        let camera = app.world_mut().spawn(Underwater::default()).id();
+
+        assert!(underwater_at(&mut app, camera, 8.));
+        assert!(!underwater_at(&mut app, camera, 30.));
+        assert!(underwater_at(&mut app, camera, 2.));
+    }
+}
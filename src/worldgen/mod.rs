@@ -0,0 +1,80 @@
+mod noise;
+mod terrain;
+
+use bevy::ecs::system::SystemParam;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::tasks::futures::check_ready;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+
+use crate::chunk::BoxChunk;
+use crate::chunk::map::ChunkPos;
+use crate::{ChunkAssets, spawn_chunk};
+
+pub use terrain::{generate, light_loaded};
+
+pub struct WorldGenPlugin;
+
+impl Plugin for WorldGenPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldSeed>()
+            .init_resource::<Generating>()
+            .add_systems(Update, spawn_generated_chunks);
+    }
+}
+
+/// Seed of the world, every chunk is generated from it and its position alone
+#[derive(Resource, Default, Clone, Copy, Deref)]
+pub struct WorldSeed(pub u32);
+
+/// Chunks being generated
+#[derive(Resource, Default)]
+pub struct Generating(HashMap<IVec3, Task<BoxChunk>>);
+
+/// Generates chunks on request, spawning each once it's done
+#[derive(SystemParam)]
+pub struct WorldGen<'w> {
+    seed: Res<'w, WorldSeed>,
+    generating: ResMut<'w, Generating>,
+}
+
+impl WorldGen<'_> {
+    /// Starts generating chunk `pos` unless it already is
+    pub fn request(&mut self, pos: ChunkPos) {
+        let seed = **self.seed;
+        self.generating.0.entry(*pos).or_insert_with(|| {
+            AsyncComputeTaskPool::get().spawn(async move { generate(seed, pos) })
+        });
+    }
+
+    #[inline]
+    pub fn is_generating(&self, pos: IVec3) -> bool {
+        self.generating.0.contains_key(&pos)
+    }
+
+    /// How many chunks are being generated
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.generating.0.len()
+    }
+
+    /// Cancels generating every chunk `keep` is false for
+    pub fn retain(&mut self, mut keep: impl FnMut(IVec3) -> bool) {
+        self.generating.0.retain(|&pos, _| keep(pos));
+    }
+}
+
+/// Spawns every chunk whose generation finished
+pub fn spawn_generated_chunks(
+    mut commands: Commands,
+    mut generating: ResMut<Generating>,
+    assets: Res<ChunkAssets>,
+) {
+    generating.0.retain(|&pos, task| {
+        let Some(chunk) = check_ready(task) else {
+            return true;
+        };
+        spawn_chunk(&mut commands, &assets, ChunkPos(pos), chunk);
+        false
+    });
+}
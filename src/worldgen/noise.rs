@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+
+/// Directions to the edges of a cube, the gradients of [`perlin3`]
+const GRAD3: [Vec3; 12] = [
+    vec3(1., 1., 0.),
+    vec3(-1., 1., 0.),
+    vec3(1., -1., 0.),
+    vec3(-1., -1., 0.),
+    vec3(1., 0., 1.),
+    vec3(-1., 0., 1.),
+    vec3(1., 0., -1.),
+    vec3(-1., 0., -1.),
+    vec3(0., 1., 1.),
+    vec3(0., -1., 1.),
+    vec3(0., 1., -1.),
+    vec3(0., -1., -1.),
+];
+
+/// Gradients of [`perlin2`]
+const GRAD2: [Vec2; 8] = [
+    vec2(1., 0.),
+    vec2(-1., 0.),
+    vec2(0., 1.),
+    vec2(0., -1.),
+    vec2(0.70710677, 0.70710677),
+    vec2(-0.70710677, 0.70710677),
+    vec2(0.70710677, -0.70710677),
+    vec2(-0.70710677, -0.70710677),
+];
+
+/// Bits of lattice point `p` under `seed`, the same every time
+#[inline]
+fn hash(seed: u32, p: IVec3) -> u32 {
+    let mut h = seed
+        ^ (p.x as u32).wrapping_mul(0x8da6_b343)
+        ^ (p.y as u32).wrapping_mul(0xd816_3841)
+        ^ (p.z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    h
+}
+
+/// Smootherstep, so the noise is smooth across cells
+#[inline]
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+/// Gradient noise in about `-1.0..1.0` with a feature about every unit
+pub fn perlin2(seed: u32, p: Vec2) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let cell = cell.as_ivec2();
+    let u = f.map(fade);
+
+    let corner = |x: i32, y: i32| {
+        let o = ivec2(x, y);
+        let grad = GRAD2[(hash(seed, (cell + o).extend(0)) % 8) as usize];
+        grad.dot(f - o.as_vec2())
+    };
+
+    let y0 = corner(0, 0).lerp(corner(1, 0), u.x);
+    let y1 = corner(0, 1).lerp(corner(1, 1), u.x);
+    y0.lerp(y1, u.y) * std::f32::consts::SQRT_2
+}
+
+/// Gradient noise in about `-1.0..1.0` with a feature about every unit
+pub fn perlin3(seed: u32, p: Vec3) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let cell = cell.as_ivec3();
+    let u = f.map(fade);
+
+    let corner = |x: i32, y: i32, z: i32| {
+        let o = ivec3(x, y, z);
+        let grad = GRAD3[(hash(seed, cell + o) % 12) as usize];
+        grad.dot(f - o.as_vec3())
+    };
+
+    let z0 = {
+        let y0 = corner(0, 0, 0).lerp(corner(1, 0, 0), u.x);
+        let y1 = corner(0, 1, 0).lerp(corner(1, 1, 0), u.x);
+        y0.lerp(y1, u.y)
+    };
+    let z1 = {
+        let y0 = corner(0, 0, 1).lerp(corner(1, 0, 1), u.x);
+        let y1 = corner(0, 1, 1).lerp(corner(1, 1, 1), u.x);
+        y0.lerp(y1, u.y)
+    };
+    z0.lerp(z1, u.z)
+}
+
+/// `octaves` layers of [`perlin2`], each twice the frequency and half the amplitude of the last,
+/// normalized back to about `-1.0..1.0`
+pub fn fbm2(seed: u32, p: Vec2, octaves: u32) -> f32 {
+    let mut sum = 0.;
+    let mut amplitude = 1.;
+    let mut total = 0.;
+    for octave in 0..octaves {
+        sum += perlin2(seed.wrapping_add(octave), p * (1 << octave) as f32) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+    }
+    sum / total
+}
@@ -0,0 +1,114 @@
+use bevy::prelude::*;
+
+use super::noise::{fbm2, perlin3};
+use crate::chunk::map::ChunkPos;
+use crate::chunk::{BoxChunk, Chunk, ChunkSize, DefaultSize, Voxel};
+
+/// Everything below this that isn't terrain is water
+pub const SEA_LEVEL: i32 = 0;
+
+/// Height of the terrain where there's no mountain or valley
+const BASE_HEIGHT: f32 = 8.;
+/// Rolling continents and basins, most of the height
+const CONTINENT_SCALE: f32 = 1. / 384.;
+const CONTINENT_HEIGHT: f32 = 40.;
+/// Hills on top of those
+const HILL_SCALE: f32 = 1. / 96.;
+const HILL_HEIGHT: f32 = 12.;
+
+const RIVER_SCALE: f32 = 1. / 512.;
+/// How close to 0 the river noise has to be to carve, so about the width of a river
+const RIVER_WIDTH: f32 = 0.035;
+/// Riverbeds are this far below the sea so they fill with water
+const RIVER_DEPTH: f32 = 3.;
+
+const CAVE_SCALE: f32 = 1. / 48.;
+/// How close to 0 both tunnel noises have to be to carve a tunnel
+const TUNNEL_WIDTH: f32 = 0.08;
+const CAVERN_SCALE: f32 = 1. / 64.;
+/// Cavern noise above this is carved out
+const CAVERN_THRESHOLD: f32 = 0.45;
+/// Solid voxels kept between caves and the surface, so lakes and rivers don't drain into them
+const CAVE_ROOF: i32 = 6;
+
+/// Height of the surface at world column `p`, the highest solid voxel before caves
+fn surface_height(seed: u32, p: IVec2) -> i32 {
+    let p = p.as_vec2();
+
+    let continent = fbm2(seed, p * CONTINENT_SCALE, 4) * CONTINENT_HEIGHT;
+    let hills = fbm2(seed.wrapping_add(16), p * HILL_SCALE, 3) * HILL_HEIGHT;
+    let height = BASE_HEIGHT + continent + hills;
+
+    // rivers follow where the noise crosses 0, deepening towards the middle
+    let river = fbm2(seed.wrapping_add(32), p * RIVER_SCALE, 3).abs();
+    let height = if river < RIVER_WIDTH {
+        let t = 1. - river / RIVER_WIDTH;
+        let t = t * t * (3. - 2. * t);
+        height.min(height.lerp(SEA_LEVEL as f32 - RIVER_DEPTH, t))
+    } else {
+        height
+    };
+
+    height.floor() as i32
+}
+
+/// Whether world voxel `p` is carved out by tunnels or caverns
+fn is_cave(seed: u32, p: IVec3) -> bool {
+    let p = p.as_vec3();
+
+    let tunnel = perlin3(seed.wrapping_add(48), p * CAVE_SCALE).abs() < TUNNEL_WIDTH
+        && perlin3(seed.wrapping_add(49), p * CAVE_SCALE).abs() < TUNNEL_WIDTH;
+    tunnel || perlin3(seed.wrapping_add(50), p * CAVERN_SCALE) > CAVERN_THRESHOLD
+}
+
+/// World voxel `p` in a column whose surface is at `surface`
+fn voxel_at(seed: u32, p: IVec3, surface: i32) -> Option<Voxel> {
+    if p.y > surface {
+        (p.y <= SEA_LEVEL).then_some(Voxel::Liquid)
+    } else if p.y < surface - CAVE_ROOF && is_cave(seed, p) {
+        None
+    } else {
+        Some(Voxel::Solid)
+    }
+}
+
+/// Surface heights of the columns of a chunk at `origin`, padding included, X varying fastest
+fn surfaces(seed: u32, origin: IVec3) -> Vec<i32> {
+    let len = DefaultSize::LEN_U32;
+    (0..len * len)
+        .map(|i| {
+            surface_height(
+                seed,
+                origin.xz() + ivec2((i % len) as i32, (i / len) as i32),
+            )
+        })
+        .collect()
+}
+
+/// Lights a chunk at `origin` with the sky over every column whose surface is below its top
+fn light(chunk: &mut Chunk, origin: IVec3, surfaces: &[i32]) {
+    let len = DefaultSize::LEN_U32;
+    let top = origin.y + len as i32 - 1;
+    chunk.enable_light(|[x, z]| surfaces[(x + z * len) as usize] < top);
+}
+
+/// Chunk `pos` of the world of `seed`, padding included so it meshes against its neighbours
+pub fn generate(seed: u32, pos: ChunkPos) -> BoxChunk {
+    let origin = pos.origin();
+    let surfaces = surfaces(seed, origin);
+
+    let mut chunk = Chunk::from_fn(|[x, y, z]| {
+        let surface = surfaces[(x + z * DefaultSize::LEN_U32) as usize];
+        voxel_at(seed, origin + uvec3(x, y, z).as_ivec3(), surface)
+    });
+    light(&mut chunk, origin, &surfaces);
+
+    chunk.into()
+}
+
+/// Lights chunk `pos` of the world of `seed` loaded without its light, the sky coming down the
+/// same columns as when it was generated
+pub fn light_loaded(seed: u32, pos: ChunkPos, chunk: &mut Chunk) {
+    let origin = pos.origin();
+    light(chunk, origin, &surfaces(seed, origin));
+}